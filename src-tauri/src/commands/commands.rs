@@ -1,22 +1,94 @@
 use crate::config::config::{load_config, save_config, Config};
 use crate::discord::rpc::DiscordRpcService;
+use crate::music::artwork::write_atomically;
+use crate::music::browse::{AlbumDetail, AlbumSummary, ArtistDetail, ArtistSummary};
+use crate::music::catalog::{AnalysisSummary, LibraryCatalog};
+use crate::music::doctor::{diagnose_library, LibraryDoctorReport};
+use crate::music::duplicates::{
+    self, DuplicateGroup, DuplicateResolution, DEFAULT_DURATION_TOLERANCE_MS,
+};
+use crate::music::fingerprint::{self, fingerprint_file, AcoustIdMatch};
 use crate::music::history::{ContinueListeningItem, ListeningHistoryStore, ListeningSource};
-use crate::music::library::MusicLibrary;
+use crate::music::importer::{self, ImportReport, ImportSource};
+use crate::music::library::{LibraryUpdate, MovedTrack, MusicLibrary, ReindexOutcome};
+use crate::music::listing::{TrackListOptions, TrackPage, TrackSortKey};
+use crate::music::lyrics::{read_lyrics, Lyrics};
 use crate::music::playback::{PlaybackService, PlaybackState};
+use crate::music::query::QueryError;
+use crate::music::reconcile::{self, MissingTrack, PrefixRewrite};
+use crate::music::scanner::{library_roots, ScanReport};
+use crate::music::search::DEFAULT_SEARCH_LIMIT;
+use crate::music::tag_editor::{self, TagEdit, TagEditFailure, TagEditPreview, TagWriteReport};
+use crate::music::watcher::{LibraryWatcher, LIBRARY_CHANGED_EVENT};
+use crate::playlists::smart::{self, SmartPlaylistDefinition};
 use crate::playlists::store::PlaylistStore;
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use tauri::State;
+use std::thread;
+use tauri::{AppHandle, Emitter, Manager, State};
 
 const HOME_SECTION_LIMIT: usize = 24;
+const REINDEX_PROGRESS_EVENT: &str = "library-reindex-progress";
+const REINDEX_FINISHED_EVENT: &str = "library-reindex-finished";
+const REINDEX_CANCELLED_EVENT: &str = "library-reindex-cancelled";
+const ANALYSIS_FINISHED_EVENT: &str = "library-analysis-finished";
 
 #[tauri::command]
-pub fn search_music(query: String, state: State<MusicLibrary>) -> Vec<crate::models::models::Song> {
-    state.search(&query)
+pub fn search_music(
+    query: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    state: State<MusicLibrary>,
+) -> Result<Vec<crate::models::models::Song>, QueryError> {
+    // An empty query lists the whole library unless the caller asks for less.
+    let limit = match limit {
+        Some(limit) => Some(limit),
+        None if query.trim().is_empty() => None,
+        None => Some(DEFAULT_SEARCH_LIMIT),
+    };
+    state.search(&query, offset.unwrap_or(0), limit)
+}
+
+#[tauri::command]
+pub fn list_tracks(
+    options: TrackListOptions,
+    state: State<MusicLibrary>,
+    history: State<ListeningHistoryStore>,
+) -> Result<TrackPage, QueryError> {
+    let play_counts = match options.sort {
+        TrackSortKey::PlayCount => history.play_counts(),
+        _ => HashMap::new(),
+    };
+    state.list_tracks(&options, &play_counts)
+}
+
+#[tauri::command]
+pub fn get_albums(state: State<MusicLibrary>) -> Vec<AlbumSummary> {
+    state.albums()
+}
+
+#[tauri::command]
+pub fn get_album(album_key: String, state: State<MusicLibrary>) -> Result<AlbumDetail, String> {
+    state
+        .album(&album_key)
+        .ok_or_else(|| "Album was not found in indexed library".to_string())
+}
+
+#[tauri::command]
+pub fn get_artists(state: State<MusicLibrary>) -> Vec<ArtistSummary> {
+    state.artists()
+}
+
+#[tauri::command]
+pub fn get_artist(artist_key: String, state: State<MusicLibrary>) -> Result<ArtistDetail, String> {
+    state
+        .artist(&artist_key)
+        .ok_or_else(|| "Artist was not found in indexed library".to_string())
 }
 
 #[tauri::command]
@@ -24,9 +96,275 @@ pub fn get_music_stats(state: State<MusicLibrary>) -> usize {
     state.get_stats()
 }
 
+/// With `match_audio` set, copies are found by audio fingerprint instead of
+/// by tags; only tracks analysed since the last scan take part.
 #[tauri::command]
-pub fn reindex_music(state: State<MusicLibrary>) -> usize {
-    state.reindex()
+pub fn find_duplicates(
+    duration_tolerance_ms: Option<u64>,
+    match_audio: Option<bool>,
+    state: State<MusicLibrary>,
+    catalog: State<LibraryCatalog>,
+) -> Result<Vec<DuplicateGroup>, String> {
+    duplicate_groups(&state, &catalog, duration_tolerance_ms, match_audio)
+}
+
+fn duplicate_groups(
+    state: &MusicLibrary,
+    catalog: &LibraryCatalog,
+    duration_tolerance_ms: Option<u64>,
+    match_audio: Option<bool>,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let tolerance_ms = duration_tolerance_ms.unwrap_or(DEFAULT_DURATION_TOLERANCE_MS);
+    if match_audio.unwrap_or(false) {
+        let fingerprints = catalog.fingerprints()?;
+        let library = state.library.lock().unwrap();
+        return Ok(duplicates::find_fingerprint_duplicates(
+            &library,
+            &fingerprints,
+            tolerance_ms,
+        ));
+    }
+    let library = state.library.lock().unwrap();
+    Ok(duplicates::find_duplicates(&library, tolerance_ms))
+}
+
+/// Identifies the track through AcoustID and MusicBrainz. Needs online
+/// requests enabled and an AcoustID key in the config.
+#[tauri::command]
+pub fn lookup_acoustid(
+    path: String,
+    library: State<MusicLibrary>,
+    catalog: State<LibraryCatalog>,
+) -> Result<Vec<AcoustIdMatch>, String> {
+    let config = load_config();
+    if !config.online_requests {
+        return Err("Online requests are disabled".to_string());
+    }
+    let client_key = config.acoustid_api_key.trim();
+    if client_key.is_empty() {
+        return Err("No AcoustID API key is configured".to_string());
+    }
+    let Some(song) = library.by_path(&path) else {
+        return Err("Track was not found in indexed library".to_string());
+    };
+
+    let fingerprint = match catalog.fingerprint(&path)? {
+        Some(fingerprint) => fingerprint,
+        None => fingerprint_file(Path::new(&path))?,
+    };
+    let client = Client::builder()
+        .user_agent("Rift/1.0")
+        .build()
+        .map_err(|error| error.to_string())?;
+    fingerprint::lookup_acoustid(&client, client_key, &fingerprint, song.duration_ms / 1000)
+}
+
+#[tauri::command]
+pub fn run_library_doctor(state: State<MusicLibrary>) -> LibraryDoctorReport {
+    let library = state.library.lock().unwrap();
+    diagnose_library(&library)
+}
+
+/// Takes the same options as `find_duplicates`, so the groups are found
+/// again the same way and only copies from the group of `keep` are removed.
+#[tauri::command]
+pub fn resolve_duplicates(
+    keep: String,
+    remove: Vec<String>,
+    duration_tolerance_ms: Option<u64>,
+    match_audio: Option<bool>,
+    app: AppHandle,
+    state: State<MusicLibrary>,
+) -> Result<DuplicateResolution, String> {
+    let catalog = app.state::<LibraryCatalog>();
+    let groups = duplicate_groups(&state, &catalog, duration_tolerance_ms, match_audio)?;
+    let resolution = duplicates::resolve_duplicates(
+        &state,
+        &app.state::<PlaylistStore>(),
+        &app.state::<ListeningHistoryStore>(),
+        &groups,
+        &keep,
+        &remove,
+    )?;
+    if resolution.update.has_changes() {
+        if let Err(error) = app.emit(LIBRARY_CHANGED_EVENT, &resolution.update) {
+            eprintln!("Failed to emit library change: {error}");
+        }
+    }
+    Ok(resolution)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagEditResult {
+    pub batch_id: Option<String>,
+    pub updated: Vec<crate::models::models::Song>,
+    pub failed: Vec<TagEditFailure>,
+}
+
+fn finish_tag_write(
+    app: &AppHandle,
+    state: &MusicLibrary,
+    report: TagWriteReport,
+) -> TagEditResult {
+    // Refresh the written files right away instead of waiting for the watcher.
+    let update = state.refresh_tracks(&report.written);
+    if update.has_changes() {
+        if let Err(error) = app.emit(LIBRARY_CHANGED_EVENT, &update) {
+            eprintln!("Failed to emit library change: {error}");
+        }
+    }
+
+    TagEditResult {
+        batch_id: report.batch_id,
+        updated: report
+            .written
+            .iter()
+            .filter_map(|path| state.by_path(path))
+            .collect(),
+        failed: report.failed,
+    }
+}
+
+fn ensure_library_tracks(state: &MusicLibrary, paths: &[String]) -> Result<(), String> {
+    match paths.iter().find(|path| state.by_path(path).is_none()) {
+        Some(path) => Err(format!("Track is not in the library: {}", path)),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub fn preview_tag_edit(
+    paths: Vec<String>,
+    edit: TagEdit,
+    state: State<MusicLibrary>,
+) -> Result<Vec<TagEditPreview>, String> {
+    ensure_library_tracks(&state, &paths)?;
+    tag_editor::preview_tag_edit(&paths, &edit)
+}
+
+#[tauri::command]
+pub fn apply_tag_edit(
+    paths: Vec<String>,
+    edit: TagEdit,
+    app: AppHandle,
+    state: State<MusicLibrary>,
+) -> Result<TagEditResult, String> {
+    ensure_library_tracks(&state, &paths)?;
+    let report = tag_editor::apply_tag_edit(&paths, &edit)?;
+    Ok(finish_tag_write(&app, &state, report))
+}
+
+#[tauri::command]
+pub fn undo_tag_edit(
+    batch_id: String,
+    app: AppHandle,
+    state: State<MusicLibrary>,
+) -> Result<TagEditResult, String> {
+    let report = tag_editor::undo_tag_edit(&batch_id)?;
+    Ok(finish_tag_write(&app, &state, report))
+}
+
+/// Plain or time-synced lyrics from a `.lrc` sidecar or the file's tags.
+#[tauri::command]
+pub fn get_lyrics(path: String, state: State<MusicLibrary>) -> Result<Option<Lyrics>, String> {
+    ensure_library_tracks(&state, std::slice::from_ref(&path))?;
+    read_lyrics(Path::new(&path))
+}
+
+#[tauri::command]
+pub fn reindex_music(app: AppHandle) -> bool {
+    start_reindex(app, false)
+}
+
+#[tauri::command]
+pub fn cancel_reindex(state: State<MusicLibrary>) -> bool {
+    state.cancel_reindex()
+}
+
+/// Folders and files the last full scan could not read.
+#[tauri::command]
+pub fn get_scan_report(state: State<MusicLibrary>) -> ScanReport {
+    state.scan_report()
+}
+
+/// Runs a library scan on a background thread, reporting progress through
+/// events. With `restart` set, a scan that is already running is cancelled
+/// and replaced so it picks up changed library roots.
+pub fn start_reindex(app: AppHandle, restart: bool) -> bool {
+    let library = app.state::<MusicLibrary>();
+    if library.is_reindexing() {
+        if !restart {
+            return false;
+        }
+        library.cancel_reindex();
+    }
+    let request = library.request_reindex();
+
+    thread::spawn(move || {
+        let library = app.state::<MusicLibrary>();
+        let outcome = library.reindex_with(request, |progress| {
+            let _ = app.emit(REINDEX_PROGRESS_EVENT, progress);
+        });
+
+        match outcome {
+            ReindexOutcome::Finished(summary) => {
+                library.relink_references(
+                    &app.state::<PlaylistStore>(),
+                    &app.state::<ListeningHistoryStore>(),
+                );
+                let update = LibraryUpdate {
+                    summary: summary.clone(),
+                    moved: Vec::new(),
+                };
+                if update.has_changes() {
+                    let _ = app.emit(LIBRARY_CHANGED_EVENT, &update);
+                }
+                let _ = app.emit(REINDEX_FINISHED_EVENT, &summary);
+                if let Some(summary) = analyze_library(&app) {
+                    let _ = app.emit(ANALYSIS_FINISHED_EVENT, &summary);
+                }
+            }
+            ReindexOutcome::Cancelled => {
+                let _ = app.emit(REINDEX_CANCELLED_EVENT, ());
+            }
+            // The newer request reports for both.
+            ReindexOutcome::Superseded => {}
+        }
+    });
+
+    true
+}
+
+/// Runs `analyze_library` in the background, e.g. after the watcher applied
+/// a batch of changes, and reports the result like a rescan does.
+pub fn queue_library_analysis(app: AppHandle) {
+    thread::spawn(move || {
+        if let Some(summary) = analyze_library(&app) {
+            let _ = app.emit(ANALYSIS_FINISHED_EVENT, &summary);
+        }
+    });
+}
+
+/// Fingerprints new and changed tracks. Fingerprints of files that are gone
+/// are kept while playlists or history still point at them, so the files
+/// can be recognised once they show up again.
+fn analyze_library(app: &AppHandle) -> Option<AnalysisSummary> {
+    let mut referenced: HashSet<String> = app
+        .state::<ListeningHistoryStore>()
+        .track_references()
+        .into_iter()
+        .filter_map(|record| record.track_id)
+        .collect();
+    match app.state::<PlaylistStore>().track_references() {
+        Ok(records) => referenced.extend(records.into_iter().filter_map(|record| record.track_id)),
+        Err(error) => {
+            eprintln!("Skipping library analysis: {}", error);
+            return None;
+        }
+    }
+
+    app.state::<LibraryCatalog>()
+        .analyze(&app.state::<MusicLibrary>(), &referenced)
 }
 
 #[tauri::command]
@@ -39,10 +377,12 @@ pub fn playback_load_and_play(
     history: State<ListeningHistoryStore>,
 ) -> Result<PlaybackState, String> {
     let playback_state = playback.load_and_play(path.clone())?;
-    if let Err(error) = history.record_play(&path, source) {
+    let song = library.by_path(&path);
+    let track_id = song.as_ref().map(|song| song.track_id.clone());
+    if let Err(error) = history.record_play(&path, track_id, source) {
         eprintln!("Failed to persist listening history: {error}");
     }
-    rpc.set_track(library.by_path(&path));
+    rpc.set_track(song);
     rpc.sync_playback(
         playback_state.is_playing,
         playback_state.current_time,
@@ -134,10 +474,72 @@ pub fn get_app_config() -> Config {
 }
 
 #[tauri::command]
-pub fn set_app_config(config: Config, rpc: State<DiscordRpcService>) -> Config {
+pub fn set_app_config(
+    config: Config,
+    rpc: State<DiscordRpcService>,
+    watcher: State<LibraryWatcher>,
+) -> Config {
     save_config(&config);
     rpc.set_enabled(config.discord_rpc);
+    watcher.set_roots(library_roots(&config));
+    config
+}
+
+#[tauri::command]
+pub fn add_library_root(
+    path: String,
+    app: AppHandle,
+    watcher: State<LibraryWatcher>,
+) -> Result<Config, String> {
+    let root = PathBuf::from(path.trim());
+    if !root.is_dir() {
+        return Err("Folder does not exist".to_string());
+    }
+    let root = root
+        .canonicalize()
+        .map_err(|error| error.to_string())?
+        .to_string_lossy()
+        .to_string();
+
+    let mut config = load_config();
+    if config.library_roots.contains(&root) {
+        return Ok(config);
+    }
+
+    config.library_roots.push(root);
+    save_config(&config);
+    watcher.set_roots(library_roots(&config));
+    start_reindex(app, true);
+    Ok(config)
+}
+
+#[tauri::command]
+pub fn remove_library_root(
+    path: String,
+    app: AppHandle,
+    watcher: State<LibraryWatcher>,
+) -> Result<Config, String> {
+    // Roots are stored canonicalized by `add_library_root`; a folder that
+    // no longer exists can still be removed by its stored path.
+    let path = path.trim();
+    let canonical = Path::new(path)
+        .canonicalize()
+        .map(|root| root.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string());
+
+    let mut config = load_config();
+    let before = config.library_roots.len();
     config
+        .library_roots
+        .retain(|root| root != path && root != &canonical);
+    if config.library_roots.len() == before {
+        return Err("Folder is not a library root".to_string());
+    }
+
+    save_config(&config);
+    watcher.set_roots(library_roots(&config));
+    start_reindex(app, true);
+    Ok(config)
 }
 
 #[tauri::command]
@@ -158,16 +560,30 @@ pub fn get_playlists(
         .map(|playlist| crate::models::models::Playlist {
             slug: playlist.slug,
             name: playlist.name,
+            smart: playlist.smart,
         })
         .collect())
 }
 
+/// Smart playlists are evaluated against the library and listening
+/// history on every call.
 #[tauri::command]
 pub fn get_playlist_tracks(
     playlist_slug: String,
     playlists: State<PlaylistStore>,
     library: State<MusicLibrary>,
+    history: State<ListeningHistoryStore>,
 ) -> Result<Vec<crate::models::models::Song>, String> {
+    if let Some(definition) = playlists.smart_definition(&playlist_slug)? {
+        let songs = library.library.lock().map_err(|error| error.to_string())?;
+        return Ok(smart::evaluate(
+            &definition,
+            &songs,
+            &history.track_references(),
+            chrono::Utc::now().timestamp(),
+        ));
+    }
+
     let paths = playlists.get_track_paths(&playlist_slug)?;
     let mut songs = Vec::new();
 
@@ -180,6 +596,110 @@ pub fn get_playlist_tracks(
     Ok(songs)
 }
 
+#[tauri::command]
+pub fn create_smart_playlist(
+    name: String,
+    definition: SmartPlaylistDefinition,
+    playlists: State<PlaylistStore>,
+) -> Result<crate::models::models::Playlist, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Playlist name is required".to_string());
+    }
+    let slug = playlists.create_smart_playlist(name, &definition)?;
+    Ok(crate::models::models::Playlist {
+        slug,
+        name: name.to_string(),
+        smart: true,
+    })
+}
+
+#[tauri::command]
+pub fn update_smart_playlist(
+    playlist_slug: String,
+    name: String,
+    definition: SmartPlaylistDefinition,
+    playlists: State<PlaylistStore>,
+) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Playlist name is required".to_string());
+    }
+    playlists.update_smart_playlist(&playlist_slug, name, &definition)
+}
+
+#[tauri::command]
+pub fn get_smart_playlist(
+    playlist_slug: String,
+    playlists: State<PlaylistStore>,
+) -> Result<Option<SmartPlaylistDefinition>, String> {
+    playlists.smart_definition(&playlist_slug)
+}
+
+#[tauri::command]
+pub fn delete_playlist(
+    playlist_slug: String,
+    playlists: State<PlaylistStore>,
+) -> Result<bool, String> {
+    playlists.delete_playlist(&playlist_slug)
+}
+
+#[tauri::command]
+pub fn find_missing_tracks(
+    library: State<MusicLibrary>,
+    playlists: State<PlaylistStore>,
+    history: State<ListeningHistoryStore>,
+    catalog: State<LibraryCatalog>,
+) -> Result<Vec<MissingTrack>, String> {
+    reconcile::find_missing_tracks(&library, &playlists, &history, &catalog)
+}
+
+#[tauri::command]
+pub fn relocate_tracks(
+    moves: Vec<MovedTrack>,
+    library: State<MusicLibrary>,
+    playlists: State<PlaylistStore>,
+    history: State<ListeningHistoryStore>,
+) -> Result<usize, String> {
+    reconcile::relocate_tracks(&library, &playlists, &history, &moves)
+}
+
+/// With `dry_run` set, only reports which entries would change.
+#[tauri::command]
+pub fn rewrite_path_prefix(
+    from: String,
+    to: String,
+    dry_run: bool,
+    library: State<MusicLibrary>,
+    playlists: State<PlaylistStore>,
+    history: State<ListeningHistoryStore>,
+) -> Result<PrefixRewrite, String> {
+    reconcile::rewrite_path_prefix(&library, &playlists, &history, &from, &to, dry_run)
+}
+
+/// `playlists_path` is only read for Rhythmbox and defaults to the
+/// `playlists.xml` next to `path`.
+#[tauri::command]
+pub fn import_library(
+    source: ImportSource,
+    path: String,
+    playlists_path: Option<String>,
+    dry_run: bool,
+    library: State<MusicLibrary>,
+    playlists: State<PlaylistStore>,
+    history: State<ListeningHistoryStore>,
+) -> Result<ImportReport, String> {
+    importer::import_library(
+        &library,
+        &playlists,
+        &history,
+        source,
+        Path::new(&path),
+        playlists_path.as_deref().map(Path::new),
+        dry_run,
+    )
+}
+
 #[tauri::command]
 pub fn add_track_to_playlist(
     playlist_slug: String,
@@ -187,11 +707,11 @@ pub fn add_track_to_playlist(
     playlists: State<PlaylistStore>,
     library: State<MusicLibrary>,
 ) -> Result<bool, String> {
-    if library.by_path(&track_path).is_none() {
+    let Some(song) = library.by_path(&track_path) else {
         return Err("Track was not found in indexed library".to_string());
-    }
+    };
 
-    playlists.add_track(&playlist_slug, &track_path)
+    playlists.add_track(&playlist_slug, &track_path, Some(&song.track_id))
 }
 
 #[tauri::command]
@@ -229,7 +749,7 @@ struct DeezerArtistItem {
     picture: Option<String>,
 }
 
-fn artist_images_dir() -> Result<PathBuf, String> {
+pub fn artist_images_dir() -> Result<PathBuf, String> {
     let dir = match dirs::cache_dir() {
         Some(mut cache) => {
             cache.push("me.wdkq.rift");
@@ -304,7 +824,8 @@ fn ensure_artist_image_cached(
             .bytes()
             .map_err(|error| error.to_string())?;
         let image_filename = format!("{}.img", key);
-        fs::write(dir.join(&image_filename), &bytes).map_err(|error| error.to_string())?;
+        write_atomically(&dir.join(&image_filename), |file| file.write_all(&bytes))
+            .map_err(|error| error.to_string())?;
         return Ok(Some(image_filename));
     }
 
@@ -1,3 +1,4 @@
+use crate::models::models::{CoverPriority, SymlinkPolicy};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
@@ -14,6 +15,24 @@ pub struct Config {
     pub online_requests: bool,
     pub automatic_updates: bool,
     pub server_url: String,
+    pub library_roots: Vec<String>,
+    pub excluded_patterns: Vec<String>,
+    pub min_duration_seconds: u64,
+    pub quarantine_dir: String,
+    /// Image names looked up next to the audio, in priority order.
+    pub folder_cover_names: Vec<String>,
+    pub cover_priority: CoverPriority,
+    /// Split artist credits such as "A feat. B" into separate artists. `,`
+    /// is left out by default since it also appears in "Lastname, Firstname"
+    /// credits.
+    pub artist_separators: Vec<String>,
+    /// Artist names that contain a separator but are one act.
+    pub artist_separator_exceptions: Vec<String>,
+    pub symlink_policy: SymlinkPolicy,
+    /// Scan dot-folders and files, and ones with the hidden attribute.
+    pub scan_hidden: bool,
+    /// Application key for AcoustID lookups; they are off while it is empty.
+    pub acoustid_api_key: String,
 }
 
 impl Default for Config {
@@ -28,10 +47,56 @@ impl Default for Config {
             online_requests: true,
             automatic_updates: true,
             server_url: "https://example.com".to_string(),
+            library_roots: vec![default_library_root().to_string_lossy().to_string()],
+            excluded_patterns: Vec::new(),
+            min_duration_seconds: 0,
+            quarantine_dir: default_quarantine_dir().to_string_lossy().to_string(),
+            folder_cover_names: ["cover", "folder", "front", "album", "albumart"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            cover_priority: CoverPriority::Embedded,
+            artist_separators: ["feat.", "ft.", "featuring", "&", ";", "/"]
+                .iter()
+                .map(|separator| separator.to_string())
+                .collect(),
+            artist_separator_exceptions: [
+                "Simon & Garfunkel",
+                "Hall & Oates",
+                "Earth, Wind & Fire",
+                "Crosby, Stills, Nash & Young",
+                "Emerson, Lake & Palmer",
+                "Blood, Sweat & Tears",
+                "Mumford & Sons",
+                "Belle & Sebastian",
+                "Florence + the Machine",
+                "AC/DC",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+            symlink_policy: SymlinkPolicy::Follow,
+            scan_hidden: false,
+            acoustid_api_key: String::new(),
         }
     }
 }
 
+pub fn default_library_root() -> PathBuf {
+    dirs::audio_dir()
+        .or_else(|| dirs::home_dir().map(|p| p.join("Music")))
+        .unwrap_or_else(|| PathBuf::from("Music"))
+}
+
+/// Duplicates set aside by the duplicate finder. Kept outside the default
+/// library root so the watcher does not index them again.
+pub fn default_quarantine_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .map(|p| p.join("Rift"))
+        .unwrap_or_else(get_config_path)
+        .join("Quarantine")
+}
+
 pub fn get_config_path() -> PathBuf {
     let mut config_dir = dirs::config_dir()
         .or_else(|| dirs::data_local_dir())
@@ -7,15 +7,19 @@ mod discord;
 mod models;
 mod music;
 mod playlists;
+mod protocol;
 
 use commands::commands::*;
 use discord::rpc::DiscordRpcService;
+use music::catalog::LibraryCatalog;
 use music::history::ListeningHistoryStore;
 use music::library::MusicLibrary;
 use music::playback::PlaybackService;
+use music::watcher::LibraryWatcher;
 use playlists::store::PlaylistStore;
+use protocol::protocol::{handle_image_request, IMAGE_SCHEME};
 
-use tauri_plugin_fs::init;
+use tauri::Manager;
 
 fn main() {
     #[cfg(target_os = "linux")]
@@ -28,22 +32,46 @@ fn main() {
     }
 
     let music_library = MusicLibrary::new();
-    let playback_service = PlaybackService::start();
     let discord_rpc_service = DiscordRpcService::start();
     let playlist_store = PlaylistStore::new();
     let listening_history = ListeningHistoryStore::new();
+    let library_catalog = LibraryCatalog::new();
+    music_library.set_track_stats(playlist_store.track_stats().unwrap_or_default());
 
     tauri::Builder::default()
         .manage(music_library)
-        .manage(playback_service)
         .manage(discord_rpc_service)
         .manage(playlist_store)
         .manage(listening_history)
-        .plugin(init())
+        .manage(library_catalog)
+        .register_asynchronous_uri_scheme_protocol(IMAGE_SCHEME, |_ctx, request, responder| {
+            handle_image_request(request, responder)
+        })
+        .setup(|app| {
+            app.manage(PlaybackService::start(app.handle().clone()));
+            app.manage(LibraryWatcher::start(app.handle().clone()));
+            start_reindex(app.handle().clone(), false);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             search_music,
+            list_tracks,
             get_music_stats,
+            get_albums,
+            get_album,
+            get_artists,
+            get_artist,
+            find_duplicates,
+            resolve_duplicates,
+            lookup_acoustid,
+            run_library_doctor,
+            preview_tag_edit,
+            apply_tag_edit,
+            undo_tag_edit,
+            get_lyrics,
             reindex_music,
+            cancel_reindex,
+            get_scan_report,
             playback_load_and_play,
             playback_play,
             playback_pause,
@@ -54,8 +82,18 @@ fn main() {
             get_app_config,
             set_app_config,
             set_onboarding_played,
+            add_library_root,
+            remove_library_root,
             get_playlists,
             get_playlist_tracks,
+            create_smart_playlist,
+            update_smart_playlist,
+            get_smart_playlist,
+            delete_playlist,
+            find_missing_tracks,
+            relocate_tracks,
+            rewrite_path_prefix,
+            import_library,
             add_track_to_playlist,
             remove_track_from_playlist,
             get_track_playlist_memberships,
@@ -1,19 +1,88 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Song {
     pub title: String,
+    /// The file has no title tag and `title` is its file name.
+    #[serde(default)]
+    pub title_from_filename: bool,
     pub subtitle: String,
+    /// Every credited artist, split from `subtitle` or multi-valued tags.
+    pub artists: Vec<String>,
     pub album: String,
+    pub album_artist: Option<String>,
+    pub compilation: bool,
     pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<u32>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
     pub added_at: i64,
     pub duration: String,
+    pub duration_ms: u64,
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    pub codec: String,
+    pub file_size: u64,
+    pub modified_at: i64,
     pub cover: String,
+    pub cover_palette: Option<CoverPalette>,
+    pub has_lyrics: bool,
+    /// 1 to 5 stars.
+    pub rating: Option<u8>,
     pub path: String,
+    pub track_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CoverPalette {
+    /// Hex colours such as `#1e2a3b`.
+    pub dominant: String,
+    pub accent: String,
+    pub colors: Vec<String>,
+}
+
+/// Per-track data that does not live in the file's tags, keyed by track id
+/// so it follows the file when it moves.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TrackStats {
+    pub rating: Option<u8>,
+    /// Replaces the file's creation time, e.g. when carried over from
+    /// another player.
+    pub added_at: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Playlist {
     pub slug: String,
     pub name: String,
+    /// Filled by rules instead of by hand.
+    pub smart: bool,
+}
+
+/// Whether embedded artwork or a folder image wins when a track has both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverPriority {
+    #[default]
+    Embedded,
+    Folder,
+}
+
+/// Whether the scanner follows symbolic links (and junctions) it finds
+/// below a library root. The roots themselves are always followed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    Ignore,
+    /// Only links whose target lies inside one of the library roots.
+    WithinRoots,
+    #[default]
+    Follow,
 }
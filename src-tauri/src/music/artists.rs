@@ -0,0 +1,181 @@
+/// How artist credits are split into individual artists.
+#[derive(Debug, Clone, Default)]
+pub struct ArtistOptions {
+    /// Separators such as `feat.` or `;`. Ones that start with a letter only
+    /// match as whole words. Case-insensitive.
+    pub separators: Vec<String>,
+    /// Names kept whole even though they contain a separator, such as
+    /// `Simon & Garfunkel` or `AC/DC`.
+    pub exceptions: Vec<String>,
+}
+
+/// Splits every tag value into artists, dropping duplicates while keeping
+/// the credited order.
+pub fn split_artists<'a>(
+    values: impl IntoIterator<Item = &'a str>,
+    options: &ArtistOptions,
+) -> Vec<String> {
+    let mut artists: Vec<String> = Vec::new();
+    for value in values {
+        for artist in split_credit(value, options) {
+            let key = artist.to_lowercase();
+            if !artists.iter().any(|known| known.to_lowercase() == key) {
+                artists.push(artist);
+            }
+        }
+    }
+    artists
+}
+
+fn split_credit(value: &str, options: &ArtistOptions) -> Vec<String> {
+    let mut artists = Vec::new();
+    let mut start = 0;
+    let mut position = 0;
+
+    while position < value.len() {
+        let rest = &value[position..];
+        // Exceptions are skipped over as a whole so their separators stay.
+        if let Some(length) = options
+            .exceptions
+            .iter()
+            .filter_map(|exception| prefix_length(rest, exception.trim()))
+            .max()
+        {
+            position += length;
+            continue;
+        }
+
+        let separator = options
+            .separators
+            .iter()
+            .map(|separator| separator.trim())
+            .filter(|separator| !separator.is_empty())
+            .filter(|separator| {
+                !separator.starts_with(char::is_alphabetic) || is_word_start(value, position)
+            })
+            .filter_map(|separator| {
+                let length = prefix_length(rest, separator)?;
+                let word_like = separator.ends_with(|c: char| c.is_alphabetic() || c == '.');
+                let followed_by_space = value[position + length..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace);
+                (!word_like || followed_by_space).then_some(length)
+            })
+            .max();
+
+        // A credit that opens with a separator, like "Featuring Artists",
+        // is a name rather than a split.
+        match separator.filter(|_| !value[..position].trim().is_empty()) {
+            Some(length) => {
+                push_artist(&mut artists, &value[start..position]);
+                position += length;
+                start = position;
+            }
+            None => {
+                position += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+    push_artist(&mut artists, &value[start..]);
+    artists
+}
+
+fn push_artist(artists: &mut Vec<String>, raw: &str) {
+    // "Artist (feat. Guest)" leaves brackets at the split point.
+    let name = raw
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ','))
+        .to_string();
+    if !name.is_empty() {
+        artists.push(name);
+    }
+}
+
+fn is_word_start(value: &str, position: usize) -> bool {
+    value[..position]
+        .chars()
+        .next_back()
+        .is_none_or(|c| c.is_whitespace() || matches!(c, '(' | '['))
+}
+
+/// Byte length of `needle` at the start of `haystack`, ignoring case.
+fn prefix_length(haystack: &str, needle: &str) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    let mut needle_chars = needle.chars().flat_map(char::to_lowercase);
+    let mut length = 0;
+    let mut pending = needle_chars.next();
+
+    for c in haystack.chars() {
+        let Some(_) = pending else {
+            break;
+        };
+        for lower in c.to_lowercase() {
+            if pending != Some(lower) {
+                return None;
+            }
+            pending = needle_chars.next();
+        }
+        length += c.len_utf8();
+    }
+    pending.is_none().then_some(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Includes the opt-in "," separator.
+    fn options() -> ArtistOptions {
+        ArtistOptions {
+            separators: ["feat.", "ft.", "featuring", "&", ",", ";", "/"]
+                .iter()
+                .map(|separator| separator.to_string())
+                .collect(),
+            exceptions: ["Simon & Garfunkel", "Earth, Wind & Fire", "AC/DC"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+
+    fn split(value: &str) -> Vec<String> {
+        split_artists([value], &options())
+    }
+
+    #[test]
+    fn splits_on_separators() {
+        assert_eq!(split("A, B"), ["A", "B"]);
+        assert_eq!(split("A & B; C"), ["A", "B", "C"]);
+        assert_eq!(split("A feat. B"), ["A", "B"]);
+        assert_eq!(split("A (Feat. B)"), ["A", "B"]);
+    }
+
+    #[test]
+    fn word_separators_match_whole_words_only() {
+        assert_eq!(split("Daft Punk"), ["Daft Punk"]);
+        assert_eq!(split("Soft Cell"), ["Soft Cell"]);
+        assert_eq!(split("A featuring B"), ["A", "B"]);
+    }
+
+    #[test]
+    fn exceptions_stay_whole() {
+        assert_eq!(split("Earth, Wind & Fire"), ["Earth, Wind & Fire"]);
+        assert_eq!(
+            split("ac/dc & Simon & Garfunkel"),
+            ["ac/dc", "Simon & Garfunkel"]
+        );
+    }
+
+    #[test]
+    fn leading_separator_is_part_of_the_name() {
+        assert_eq!(split("Featuring Artists"), ["Featuring Artists"]);
+    }
+
+    #[test]
+    fn duplicates_are_dropped_across_values() {
+        let artists = split_artists(["A & B", "b; C"], &options());
+        assert_eq!(artists, ["A", "B", "C"]);
+    }
+}
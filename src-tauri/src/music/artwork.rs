@@ -0,0 +1,248 @@
+use crate::models::models::CoverPalette;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Edge lengths of the JPEG thumbnails generated next to every cached cover.
+pub const THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+
+const THUMBNAIL_QUALITY: u8 = 85;
+const PALETTE_SAMPLE_SIZE: u32 = 64;
+const PALETTE_COLORS: usize = 5;
+// Bucket colours to 4 bits per channel before counting.
+const QUANTIZE_SHIFT: u8 = 4;
+// Squared RGB distance below which two palette colours count as the same.
+const MIN_COLOR_DISTANCE: u32 = 48 * 48;
+
+static PALETTES: OnceLock<Mutex<HashMap<String, CoverPalette>>> = OnceLock::new();
+
+/// Cached covers are named `<sha256>.<ext>`; thumbnails and the palette
+/// share the hash so `cleanup_unused_covers` can keep them together.
+pub fn cover_hash(file_name: &str) -> &str {
+    file_name.split(['.', '-']).next().unwrap_or(file_name)
+}
+
+pub fn thumbnail_name(cover: &str, size: u32) -> String {
+    format!("{}-{}.jpg", cover_hash(cover), size)
+}
+
+fn palette_name(cover: &str) -> String {
+    format!("{}.palette.json", cover_hash(cover))
+}
+
+/// Makes sure the thumbnails and palette of a cached cover exist, creating
+/// them on first sight, and returns the palette.
+pub fn prepare_cover(cache_dir: &Path, cover: &str) -> Option<CoverPalette> {
+    if cover.is_empty() {
+        return None;
+    }
+    let palettes = PALETTES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(palette) = palettes.lock().unwrap().get(cover) {
+        return Some(palette.clone());
+    }
+
+    let palette = match read_prepared(cache_dir, cover) {
+        Some(palette) => palette,
+        None => match generate(cache_dir, cover) {
+            Ok(palette) => palette,
+            Err(error) => {
+                eprintln!("Warning: Could not prepare cover {}: {}", cover, error);
+                return None;
+            }
+        },
+    };
+
+    palettes
+        .lock()
+        .unwrap()
+        .insert(cover.to_string(), palette.clone());
+    Some(palette)
+}
+
+fn read_prepared(cache_dir: &Path, cover: &str) -> Option<CoverPalette> {
+    if THUMBNAIL_SIZES
+        .iter()
+        .any(|size| !cache_dir.join(thumbnail_name(cover, *size)).exists())
+    {
+        return None;
+    }
+    let content = fs::read_to_string(cache_dir.join(palette_name(cover))).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn generate(cache_dir: &Path, cover: &str) -> Result<CoverPalette, String> {
+    let mut image = open_image(&cache_dir.join(cover))?;
+
+    // Largest first, each size scaled from the previous one, so the full
+    // resolution original is only resampled once.
+    for size in THUMBNAIL_SIZES.iter().rev() {
+        image = scale_down(&image, *size);
+        write_jpeg(&image, &cache_dir.join(thumbnail_name(cover, *size)))
+            .map_err(|error| error.to_string())?;
+    }
+
+    let palette = extract_palette(&image);
+    let json = serde_json::to_string(&palette).map_err(|error| error.to_string())?;
+    write_atomically(&cache_dir.join(palette_name(cover)), |file| {
+        file.write_all(json.as_bytes())
+    })
+    .map_err(|error| error.to_string())?;
+    Ok(palette)
+}
+
+/// Decodes by content rather than extension; artist images are stored as
+/// `.img` and embedded covers sometimes carry the wrong MIME type.
+pub fn open_image(path: &Path) -> Result<DynamicImage, String> {
+    image::io::Reader::open(path)
+        .map_err(|error| error.to_string())?
+        .with_guessed_format()
+        .map_err(|error| error.to_string())?
+        .decode()
+        .map_err(|error| error.to_string())
+}
+
+/// Fits `image` into `size`×`size`, never scaling up.
+pub fn scale_down(image: &DynamicImage, size: u32) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    if width <= size && height <= size {
+        return image.clone();
+    }
+    // A cheap box filter gets close first; the final step keeps it sharp.
+    if width > size * 4 || height > size * 4 {
+        return image
+            .thumbnail(size * 2, size * 2)
+            .resize(size, size, FilterType::CatmullRom);
+    }
+    image.resize(size, size, FilterType::CatmullRom)
+}
+
+pub fn write_jpeg(image: &DynamicImage, destination: &Path) -> io::Result<()> {
+    write_atomically(destination, |file| {
+        JpegEncoder::new_with_quality(file, THUMBNAIL_QUALITY)
+            .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))
+            .map_err(io::Error::other)
+    })
+}
+
+/// Cached images are served as immutable and reused once they exist, so
+/// they are written to a hidden file next to `destination` and renamed into
+/// place: readers and concurrent writers never see a partial file, and a
+/// failed write leaves nothing behind.
+pub fn write_atomically(
+    destination: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    static NEXT_STAGED: AtomicU64 = AtomicU64::new(0);
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let staged = destination.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        process::id(),
+        NEXT_STAGED.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create(&staged).and_then(|file| {
+        let mut file = BufWriter::new(file);
+        write(&mut file)?;
+        file.into_inner().map_err(|error| error.into_error())?;
+        fs::rename(&staged, destination)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    result
+}
+
+/// The most common colour is the dominant one; the accent is the most
+/// common colour that is both saturated and clearly different from it.
+fn extract_palette(image: &DynamicImage) -> CoverPalette {
+    let sample = scale_down(image, PALETTE_SAMPLE_SIZE).to_rgba8();
+
+    let mut buckets: HashMap<[u8; 3], (u32, [u32; 3])> = HashMap::new();
+    for pixel in sample.pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let key = [
+            r >> QUANTIZE_SHIFT,
+            g >> QUANTIZE_SHIFT,
+            b >> QUANTIZE_SHIFT,
+        ];
+        let bucket = buckets.entry(key).or_insert((0, [0; 3]));
+        bucket.0 += 1;
+        bucket.1[0] += r as u32;
+        bucket.1[1] += g as u32;
+        bucket.1[2] += b as u32;
+    }
+
+    let mut colors: Vec<(u32, [u8; 3])> = buckets
+        .into_values()
+        .map(|(count, sum)| {
+            let average = sum.map(|channel| (channel / count) as u8);
+            (count, average)
+        })
+        .collect();
+    colors.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let mut distinct: Vec<[u8; 3]> = Vec::new();
+    for (_, color) in &colors {
+        if distinct
+            .iter()
+            .all(|kept| color_distance(kept, color) >= MIN_COLOR_DISTANCE)
+        {
+            distinct.push(*color);
+        }
+        if distinct.len() == PALETTE_COLORS {
+            break;
+        }
+    }
+
+    let dominant = distinct.first().copied().unwrap_or([0, 0, 0]);
+    let accent = colors
+        .iter()
+        .filter(|(_, color)| color_distance(&dominant, color) >= MIN_COLOR_DISTANCE)
+        .max_by(|a, b| {
+            let score = |(count, color): &(u32, [u8; 3])| *count as f32 * (0.1 + saturation(color));
+            score(a).total_cmp(&score(b))
+        })
+        .map(|(_, color)| *color)
+        .unwrap_or(dominant);
+
+    CoverPalette {
+        dominant: hex_color(&dominant),
+        accent: hex_color(&accent),
+        colors: distinct.iter().map(hex_color).collect(),
+    }
+}
+
+fn color_distance(a: &[u8; 3], b: &[u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+        .sum()
+}
+
+fn saturation(color: &[u8; 3]) -> f32 {
+    let max = *color.iter().max().unwrap_or(&0) as f32;
+    let min = *color.iter().min().unwrap_or(&0) as f32;
+    if max == 0.0 {
+        0.0
+    } else {
+        (max - min) / max
+    }
+}
+
+fn hex_color(color: &[u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
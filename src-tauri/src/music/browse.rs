@@ -0,0 +1,243 @@
+use crate::models::models::Song;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumSummary {
+    pub key: String,
+    pub title: String,
+    pub album_artist: String,
+    pub year: Option<u32>,
+    pub track_count: usize,
+    pub total_duration_ms: u64,
+    pub cover: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumDetail {
+    #[serde(flatten)]
+    pub summary: AlbumSummary,
+    pub tracks: Vec<Song>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtistSummary {
+    pub key: String,
+    pub name: String,
+    pub album_count: usize,
+    pub track_count: usize,
+    pub cover: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtistDetail {
+    #[serde(flatten)]
+    pub summary: ArtistSummary,
+    pub albums: Vec<AlbumDetail>,
+    pub appears_on: Vec<AlbumDetail>,
+}
+
+/// Artist an album is filed under. Compilations without an explicit album
+/// artist are grouped together instead of being split per track artist.
+pub fn album_artist_of(song: &Song) -> &str {
+    match song.album_artist.as_deref() {
+        Some(album_artist) => album_artist,
+        None if song.compilation => VARIOUS_ARTISTS,
+        None => &song.subtitle,
+    }
+}
+
+/// Whether `key` is one of the artists credited on `song`.
+pub fn is_credited(song: &Song, key: &str) -> bool {
+    song.artists.iter().any(|name| artist_key(name) == key)
+}
+
+pub fn artist_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+pub fn album_key(song: &Song) -> String {
+    format!(
+        "{}\u{1f}{}",
+        artist_key(album_artist_of(song)),
+        song.album.trim().to_lowercase()
+    )
+}
+
+pub fn compare_album_order(a: &Song, b: &Song) -> Ordering {
+    a.disc_number
+        .unwrap_or(1)
+        .cmp(&b.disc_number.unwrap_or(1))
+        .then_with(|| {
+            a.track_number
+                .unwrap_or(u32::MAX)
+                .cmp(&b.track_number.unwrap_or(u32::MAX))
+        })
+        .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+fn group_albums<'a>(songs: impl Iterator<Item = &'a Song>) -> HashMap<String, Vec<&'a Song>> {
+    let mut albums: HashMap<String, Vec<&Song>> = HashMap::new();
+    for song in songs {
+        albums.entry(album_key(song)).or_default().push(song);
+    }
+    albums
+}
+
+fn summarize_album(key: String, tracks: &[&Song]) -> AlbumSummary {
+    let first = tracks[0];
+    AlbumSummary {
+        key,
+        title: first.album.clone(),
+        album_artist: album_artist_of(first).to_string(),
+        year: tracks.iter().filter_map(|song| song.year).min(),
+        track_count: tracks.len(),
+        total_duration_ms: tracks.iter().map(|song| song.duration_ms).sum(),
+        cover: tracks
+            .iter()
+            .map(|song| song.cover.as_str())
+            .find(|cover| !cover.is_empty())
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+fn album_detail(key: String, mut tracks: Vec<&Song>) -> AlbumDetail {
+    tracks.sort_by(|a, b| compare_album_order(a, b));
+    AlbumDetail {
+        summary: summarize_album(key, &tracks),
+        tracks: tracks.into_iter().cloned().collect(),
+    }
+}
+
+fn compare_summaries(a: &AlbumSummary, b: &AlbumSummary) -> Ordering {
+    a.album_artist
+        .to_lowercase()
+        .cmp(&b.album_artist.to_lowercase())
+        .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+}
+
+fn compare_discography(a: &AlbumDetail, b: &AlbumDetail) -> Ordering {
+    a.summary
+        .year
+        .unwrap_or(u32::MAX)
+        .cmp(&b.summary.year.unwrap_or(u32::MAX))
+        .then_with(|| compare_summaries(&a.summary, &b.summary))
+}
+
+pub fn list_albums(library: &HashMap<String, Song>) -> Vec<AlbumSummary> {
+    let mut albums: Vec<AlbumSummary> = group_albums(library.values())
+        .into_iter()
+        .map(|(key, tracks)| summarize_album(key, &tracks))
+        .collect();
+    albums.sort_by(compare_summaries);
+    albums
+}
+
+pub fn find_album(library: &HashMap<String, Song>, key: &str) -> Option<AlbumDetail> {
+    let tracks: Vec<&Song> = library
+        .values()
+        .filter(|song| album_key(song) == key)
+        .collect();
+    if tracks.is_empty() {
+        return None;
+    }
+    Some(album_detail(key.to_string(), tracks))
+}
+
+pub fn list_artists(library: &HashMap<String, Song>) -> Vec<ArtistSummary> {
+    let mut artists: HashMap<String, ArtistSummary> = HashMap::new();
+
+    for (album_key, tracks) in group_albums(library.values()) {
+        let first = tracks[0];
+        let name = album_artist_of(first);
+        let artist = artists
+            .entry(artist_key(name))
+            .or_insert_with(|| empty_artist(name));
+        artist.album_count += 1;
+        if artist.cover.is_empty() {
+            artist.cover = summarize_album(album_key, &tracks).cover;
+        }
+    }
+
+    for song in library.values() {
+        for name in &song.artists {
+            let artist = artists
+                .entry(artist_key(name))
+                .or_insert_with(|| empty_artist(name));
+            artist.track_count += 1;
+            if artist.cover.is_empty() {
+                artist.cover = song.cover.clone();
+            }
+        }
+    }
+
+    let mut artists: Vec<ArtistSummary> = artists.into_values().collect();
+    artists.sort_by_key(|artist| artist.name.to_lowercase());
+    artists
+}
+
+fn empty_artist(name: &str) -> ArtistSummary {
+    ArtistSummary {
+        key: artist_key(name),
+        name: name.to_string(),
+        album_count: 0,
+        track_count: 0,
+        cover: String::new(),
+    }
+}
+
+pub fn find_artist(library: &HashMap<String, Song>, key: &str) -> Option<ArtistDetail> {
+    let mut summary: Option<ArtistSummary> = None;
+    let mut albums = Vec::new();
+    let mut appears_on = Vec::new();
+
+    for (album_key, tracks) in group_albums(library.values()) {
+        let album_artist = album_artist_of(tracks[0]);
+        let own_album = artist_key(album_artist) == key;
+        let track_count = tracks.iter().filter(|song| is_credited(song, key)).count();
+
+        if !own_album && track_count == 0 {
+            continue;
+        }
+
+        let artist = summary.get_or_insert_with(|| {
+            let name = tracks
+                .iter()
+                .flat_map(|song| song.artists.iter().map(String::as_str))
+                .find(|name| artist_key(name) == key)
+                .unwrap_or(album_artist);
+            empty_artist(name)
+        });
+        artist.track_count += track_count;
+
+        let detail = album_detail(album_key, tracks);
+        if own_album {
+            artist.album_count += 1;
+            albums.push(detail);
+        } else {
+            appears_on.push(detail);
+        }
+    }
+
+    let mut summary = summary?;
+    albums.sort_by(compare_discography);
+    appears_on.sort_by(compare_discography);
+    summary.cover = albums
+        .iter()
+        .chain(appears_on.iter())
+        .map(|album| album.summary.cover.as_str())
+        .find(|cover| !cover.is_empty())
+        .unwrap_or_default()
+        .to_string();
+
+    Some(ArtistDetail {
+        summary,
+        albums,
+        appears_on,
+    })
+}
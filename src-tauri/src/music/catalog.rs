@@ -0,0 +1,359 @@
+use super::fingerprint::fingerprint_file;
+use super::library::MusicLibrary;
+use crate::config::config::get_config_path;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Decoding is heavy; leave cores free for playback and the UI.
+const MAX_ANALYSIS_WORKERS: usize = 4;
+
+/// Per-track analysis results that are too expensive to redo on every scan,
+/// kept in `library.db` and keyed by path. Rows stay valid while the file's
+/// size and modification time are unchanged.
+#[derive(Debug)]
+pub struct LibraryCatalog {
+    connection: Mutex<Connection>,
+    analysis_running: AtomicBool,
+    /// Set by every `analyze` call; a running analysis goes round again
+    /// while it is set, so requests made meanwhile are not lost.
+    analysis_requested: AtomicBool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CatalogFingerprint {
+    pub path: String,
+    pub track_id: String,
+    pub fingerprint: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnalysisSummary {
+    pub analyzed: usize,
+    pub failed: usize,
+    pub pruned: usize,
+    /// Tracks with a fingerprint after this run.
+    pub fingerprinted: usize,
+}
+
+struct PendingTrack {
+    path: String,
+    track_id: String,
+    file_size: u64,
+    modified_at: i64,
+}
+
+impl LibraryCatalog {
+    pub fn new() -> Self {
+        if let Err(error) = std::fs::create_dir_all(get_config_path()) {
+            panic!(
+                "Failed to create config directory for library catalog: {}",
+                error
+            );
+        }
+
+        let connection = Connection::open(get_config_path().join("library.db"))
+            .expect("Failed to open library catalog");
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS fingerprints (
+                    path TEXT PRIMARY KEY,
+                    track_id TEXT NOT NULL,
+                    file_size INTEGER NOT NULL,
+                    modified_at INTEGER NOT NULL,
+                    fingerprint BLOB,
+                    error TEXT
+                 );
+                 CREATE INDEX IF NOT EXISTS idx_fingerprints_track_id ON fingerprints(track_id);",
+            )
+            .expect("Failed to initialize library catalog schema");
+
+        Self {
+            connection: Mutex::new(connection),
+            analysis_running: AtomicBool::new(false),
+            analysis_requested: AtomicBool::new(false),
+        }
+    }
+
+    /// Every stored fingerprint, including ones of files that are gone but
+    /// still referenced by playlists or history.
+    pub fn fingerprints(&self) -> Result<Vec<CatalogFingerprint>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT path, track_id, fingerprint FROM fingerprints
+                 WHERE fingerprint IS NOT NULL",
+            )
+            .map_err(|error| error.to_string())?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok(CatalogFingerprint {
+                    path: row.get(0)?,
+                    track_id: row.get(1)?,
+                    fingerprint: from_blob(&row.get::<_, Vec<u8>>(2)?),
+                })
+            })
+            .map_err(|error| error.to_string())?;
+
+        let mut fingerprints = Vec::new();
+        for row in rows {
+            fingerprints.push(row.map_err(|error| error.to_string())?);
+        }
+        Ok(fingerprints)
+    }
+
+    pub fn fingerprint(&self, path: &str) -> Result<Option<Vec<u32>>, String> {
+        let connection = self.connection.lock().unwrap();
+        let blob: Option<Vec<u8>> = connection
+            .query_row(
+                "SELECT fingerprint FROM fingerprints
+                 WHERE path = ?1 AND fingerprint IS NOT NULL",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|error| error.to_string())?;
+        Ok(blob.map(|blob| from_blob(&blob)))
+    }
+
+    /// Fingerprints tracks that are new or changed since their last
+    /// analysis and drops rows of files that are gone, unless their track id
+    /// is in `referenced`. Stops early when a rescan starts; the next one
+    /// picks up where this left off. Returns `None` when an analysis that
+    /// is already running takes the request over.
+    pub fn analyze(
+        &self,
+        library: &MusicLibrary,
+        referenced: &HashSet<String>,
+    ) -> Option<AnalysisSummary> {
+        self.analysis_requested.store(true, Ordering::SeqCst);
+        let mut summary = None;
+        loop {
+            if self
+                .analysis_running
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                return summary;
+            }
+            while self.analysis_requested.swap(false, Ordering::SeqCst) {
+                summary = self.run_analysis(library, referenced);
+            }
+            self.analysis_running.store(false, Ordering::SeqCst);
+            // A request made between the last check and the store above.
+            if !self.analysis_requested.load(Ordering::SeqCst) {
+                return summary;
+            }
+        }
+    }
+
+    fn run_analysis(
+        &self,
+        library: &MusicLibrary,
+        referenced: &HashSet<String>,
+    ) -> Option<AnalysisSummary> {
+        let tracks: Vec<PendingTrack> = library
+            .library
+            .lock()
+            .unwrap()
+            .values()
+            .map(|song| PendingTrack {
+                path: song.path.clone(),
+                track_id: song.track_id.clone(),
+                file_size: song.file_size,
+                modified_at: song.modified_at,
+            })
+            .collect();
+        let stamps = match self.stamps() {
+            Ok(stamps) => stamps,
+            Err(error) => {
+                eprintln!("Failed to read library catalog: {}", error);
+                return None;
+            }
+        };
+
+        let mut summary = AnalysisSummary {
+            pruned: self.prune(&tracks, referenced).unwrap_or_else(|error| {
+                eprintln!("Failed to prune library catalog: {}", error);
+                0
+            }),
+            ..AnalysisSummary::default()
+        };
+        let pending: Vec<&PendingTrack> = tracks
+            .iter()
+            .filter(|track| stamps.get(&track.path) != Some(&(track.file_size, track.modified_at)))
+            .collect();
+
+        let workers = thread::available_parallelism()
+            .map(|count| count.get() / 2)
+            .unwrap_or(2)
+            .clamp(1, MAX_ANALYSIS_WORKERS);
+        let next = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+        let analyzed = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while !library.is_reindexing() {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(track) = pending.get(index) else {
+                            break;
+                        };
+                        let fingerprint = fingerprint_file(Path::new(&track.path));
+                        if let Err(error) = &fingerprint {
+                            eprintln!("Cannot fingerprint {}: {}", track.path, error);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        if let Err(error) = self.store(track, fingerprint) {
+                            eprintln!("Failed to update library catalog: {}", error);
+                        }
+                        analyzed.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        summary.analyzed = analyzed.into_inner();
+        summary.failed = failed.into_inner();
+        if library.is_reindexing() {
+            return None;
+        }
+        summary.fingerprinted = self.count_fingerprinted().unwrap_or(0);
+        println!(
+            "Analyzed {} tracks ({} failed, {} pruned, {} fingerprinted)",
+            summary.analyzed, summary.failed, summary.pruned, summary.fingerprinted
+        );
+        Some(summary)
+    }
+
+    fn stamps(&self) -> Result<HashMap<String, (u64, i64)>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT path, file_size, modified_at FROM fingerprints")
+            .map_err(|error| error.to_string())?;
+
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+            .map_err(|error| error.to_string())?;
+
+        let mut stamps = HashMap::new();
+        for row in rows {
+            let (path, stamp) = row.map_err(|error| error.to_string())?;
+            stamps.insert(path, stamp);
+        }
+        Ok(stamps)
+    }
+
+    /// Failures are stored too, so broken files are only retried once they
+    /// change.
+    fn store(
+        &self,
+        track: &PendingTrack,
+        fingerprint: Result<Vec<u32>, String>,
+    ) -> Result<(), String> {
+        let (blob, error) = match fingerprint {
+            Ok(fingerprint) => (Some(to_blob(&fingerprint)), None),
+            Err(error) => (None, Some(error)),
+        };
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO fingerprints
+                    (path, track_id, file_size, modified_at, fingerprint, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    track.path,
+                    track.track_id,
+                    track.file_size,
+                    track.modified_at,
+                    blob,
+                    error
+                ],
+            )
+            .map_err(|error| error.to_string())?;
+        Ok(())
+    }
+
+    fn prune(
+        &self,
+        tracks: &[PendingTrack],
+        referenced: &HashSet<String>,
+    ) -> Result<usize, String> {
+        let indexed: HashSet<&str> = tracks.iter().map(|track| track.path.as_str()).collect();
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+        let stale: Vec<String> = {
+            let mut statement = transaction
+                .prepare("SELECT path, track_id FROM fingerprints")
+                .map_err(|error| error.to_string())?;
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })
+                .map_err(|error| error.to_string())?;
+            let mut stale = Vec::new();
+            for row in rows {
+                let (path, track_id) = row.map_err(|error| error.to_string())?;
+                if !indexed.contains(path.as_str()) && !referenced.contains(&track_id) {
+                    stale.push(path);
+                }
+            }
+            stale
+        };
+        for path in &stale {
+            transaction
+                .execute("DELETE FROM fingerprints WHERE path = ?1", params![path])
+                .map_err(|error| error.to_string())?;
+        }
+        transaction.commit().map_err(|error| error.to_string())?;
+        Ok(stale.len())
+    }
+
+    fn count_fingerprinted(&self) -> Result<usize, String> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row(
+                "SELECT COUNT(*) FROM fingerprints WHERE fingerprint IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|error| error.to_string())
+    }
+}
+
+fn to_blob(fingerprint: &[u32]) -> Vec<u8> {
+    fingerprint
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<u32> {
+    blob.chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blobs_round_trip() {
+        let fingerprint = vec![0, 1, 0xDEAD_BEEF, u32::MAX];
+        let blob = to_blob(&fingerprint);
+
+        assert_eq!(blob.len(), 16);
+        assert_eq!(&blob[8..12], &[0xEF, 0xBE, 0xAD, 0xDE]);
+        assert_eq!(from_blob(&blob), fingerprint);
+        assert!(from_blob(&[]).is_empty());
+    }
+}
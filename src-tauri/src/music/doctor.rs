@@ -0,0 +1,280 @@
+use super::browse::{album_artist_of, album_key};
+use crate::models::models::Song;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+const UNKNOWN_ALBUM: &str = "Unknown Album";
+const MIN_PLAUSIBLE_DURATION_MS: u64 = 5_000;
+const MAX_PLAUSIBLE_DURATION_MS: u64 = 3 * 60 * 60 * 1000;
+
+/// Declared in the order findings are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    UnknownArtist,
+    UnknownAlbum,
+    FilenameTitle,
+    MissingTrackNumber,
+    TrackNumberGap,
+    InconsistentAlbumArtist,
+    InconsistentYear,
+    InconsistentCover,
+    MissingArtwork,
+    SuspiciousDuration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryIssue {
+    pub kind: IssueKind,
+    pub message: String,
+    /// Album the finding belongs to, as used by `get_album`.
+    pub album_key: Option<String>,
+    pub album: Option<String>,
+    /// Paths of the tracks involved.
+    pub tracks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryDoctorReport {
+    pub tracks_checked: usize,
+    pub albums_checked: usize,
+    pub issues: Vec<LibraryIssue>,
+}
+
+/// Looks for metadata worth fixing. Track-level problems are grouped per
+/// album so a whole untagged folder shows up as one finding.
+pub fn diagnose_library(library: &HashMap<String, Song>) -> LibraryDoctorReport {
+    let mut albums: BTreeMap<String, Vec<&Song>> = BTreeMap::new();
+    for song in library.values() {
+        albums.entry(album_key(song)).or_default().push(song);
+    }
+
+    let mut issues = Vec::new();
+    for tracks in albums.values() {
+        check_tracks(tracks, &mut issues);
+        check_album(tracks, &mut issues);
+    }
+    check_album_artists(library, &mut issues);
+
+    issues.sort_by(|a, b| {
+        a.kind
+            .cmp(&b.kind)
+            .then_with(|| a.album.cmp(&b.album))
+            .then_with(|| a.tracks.cmp(&b.tracks))
+    });
+
+    LibraryDoctorReport {
+        tracks_checked: library.len(),
+        albums_checked: albums.len(),
+        issues,
+    }
+}
+
+fn issue(kind: IssueKind, message: String, album: &[&Song], tracks: &[&Song]) -> LibraryIssue {
+    let mut paths: Vec<String> = tracks.iter().map(|song| song.path.clone()).collect();
+    paths.sort();
+    LibraryIssue {
+        kind,
+        message,
+        album_key: album.first().map(|song| album_key(song)),
+        album: album.first().map(|song| song.album.clone()),
+        tracks: paths,
+    }
+}
+
+fn count(tracks: &[&Song]) -> String {
+    match tracks.len() {
+        1 => "1 track".to_string(),
+        count => format!("{} tracks", count),
+    }
+}
+
+// The kind, how the problem reads in the message, and the test.
+type TrackCheck = (IssueKind, &'static str, fn(&Song) -> bool);
+
+fn check_tracks(album: &[&Song], issues: &mut Vec<LibraryIssue>) {
+    let checks: [TrackCheck; 5] = [
+        (IssueKind::UnknownArtist, "no artist tag", |song| {
+            song.subtitle == UNKNOWN_ARTIST
+        }),
+        (IssueKind::UnknownAlbum, "no album tag", |song| {
+            song.album == UNKNOWN_ALBUM
+        }),
+        (IssueKind::FilenameTitle, "the file name as title", |song| {
+            song.title_from_filename
+        }),
+        (IssueKind::MissingTrackNumber, "no track number", |song| {
+            song.track_number.is_none_or(|number| number == 0)
+        }),
+        (
+            IssueKind::SuspiciousDuration,
+            "an implausible length",
+            |song| {
+                !(MIN_PLAUSIBLE_DURATION_MS..=MAX_PLAUSIBLE_DURATION_MS).contains(&song.duration_ms)
+            },
+        ),
+    ];
+
+    for (kind, problem, matches) in checks {
+        let affected: Vec<&Song> = album.iter().copied().filter(|song| matches(song)).collect();
+        if affected.is_empty() {
+            continue;
+        }
+        let message = format!("{} {} {}", count(&affected), have(&affected), problem);
+        issues.push(issue(kind, message, album, &affected));
+    }
+}
+
+fn have(tracks: &[&Song]) -> &'static str {
+    if tracks.len() == 1 {
+        "has"
+    } else {
+        "have"
+    }
+}
+
+fn check_album(album: &[&Song], issues: &mut Vec<LibraryIssue>) {
+    // Loose singles are not albums; most album checks would only be noise.
+    if album.len() < 2 || album[0].album == UNKNOWN_ALBUM {
+        if album.iter().all(|song| song.cover.is_empty()) {
+            issues.push(issue(
+                IssueKind::MissingArtwork,
+                format!("{} without artwork", count(album)),
+                album,
+                album,
+            ));
+        }
+        return;
+    }
+
+    let years: BTreeSet<u32> = album.iter().filter_map(|song| song.year).collect();
+    if years.len() > 1 {
+        let years: Vec<String> = years.iter().map(u32::to_string).collect();
+        issues.push(issue(
+            IssueKind::InconsistentYear,
+            format!("Tracks disagree on the year: {}", years.join(", ")),
+            album,
+            album,
+        ));
+    }
+
+    let covers: BTreeSet<&str> = album.iter().map(|song| song.cover.as_str()).collect();
+    if covers.len() == 1 && covers.contains("") {
+        issues.push(issue(
+            IssueKind::MissingArtwork,
+            "Album has no artwork".to_string(),
+            album,
+            album,
+        ));
+    } else if covers.len() > 1 {
+        let without: Vec<&Song> = album
+            .iter()
+            .copied()
+            .filter(|song| song.cover.is_empty())
+            .collect();
+        let message = match without.len() {
+            0 => format!("Tracks use {} different covers", covers.len()),
+            _ => format!("{} {} no artwork", count(&without), have(&without)),
+        };
+        issues.push(issue(IssueKind::InconsistentCover, message, album, album));
+    }
+
+    check_numbering(album, issues);
+}
+
+/// Missing numbers per disc, counting up to the highest number or the
+/// track total, whichever is larger.
+fn check_numbering(album: &[&Song], issues: &mut Vec<LibraryIssue>) {
+    let mut discs: BTreeMap<u32, Vec<&Song>> = BTreeMap::new();
+    for song in album {
+        discs
+            .entry(song.disc_number.unwrap_or(1))
+            .or_default()
+            .push(song);
+    }
+    let multi_disc = discs.len() > 1;
+
+    for (disc, tracks) in discs {
+        let numbers: BTreeSet<u32> = tracks
+            .iter()
+            .filter_map(|song| song.track_number)
+            .filter(|number| *number > 0)
+            .collect();
+        let Some(highest) = numbers.last().copied() else {
+            continue;
+        };
+        let expected = tracks
+            .iter()
+            .filter_map(|song| song.track_total)
+            .max()
+            .unwrap_or(0)
+            .max(highest);
+        let missing: Vec<String> = (1..=expected)
+            .filter(|number| !numbers.contains(number))
+            .map(|number| number.to_string())
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        let disc_label = if multi_disc {
+            format!(" on disc {}", disc)
+        } else {
+            String::new()
+        };
+        issues.push(issue(
+            IssueKind::TrackNumberGap,
+            format!(
+                "Missing {} {} of {}{}",
+                if missing.len() == 1 {
+                    "track"
+                } else {
+                    "tracks"
+                },
+                missing.join(", "),
+                expected,
+                disc_label
+            ),
+            album,
+            &tracks,
+        ));
+    }
+}
+
+/// Albums are grouped by album artist, so tracks of one album that disagree
+/// on it end up split apart. They are found by folder and album title.
+fn check_album_artists(library: &HashMap<String, Song>, issues: &mut Vec<LibraryIssue>) {
+    let mut folders: BTreeMap<(&Path, String), Vec<&Song>> = BTreeMap::new();
+    for song in library.values() {
+        if song.album == UNKNOWN_ALBUM {
+            continue;
+        }
+        let Some(folder) = Path::new(&song.path).parent() else {
+            continue;
+        };
+        folders
+            .entry((folder, song.album.trim().to_lowercase()))
+            .or_default()
+            .push(song);
+    }
+
+    for tracks in folders.values() {
+        let artists: BTreeSet<&str> = tracks.iter().map(|song| album_artist_of(song)).collect();
+        if artists.len() < 2 {
+            continue;
+        }
+        let artists: Vec<&str> = artists.into_iter().collect();
+        issues.push(issue(
+            IssueKind::InconsistentAlbumArtist,
+            format!(
+                "Tracks are filed under {} album artists: {}",
+                artists.len(),
+                artists.join(", ")
+            ),
+            tracks,
+            tracks,
+        ));
+    }
+}
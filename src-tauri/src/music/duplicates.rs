@@ -0,0 +1,323 @@
+use super::catalog::CatalogFingerprint;
+use super::fingerprint::FingerprintIndex;
+use super::history::ListeningHistoryStore;
+use super::library::{LibraryUpdate, MovedTrack, MusicLibrary};
+use super::scanner::library_roots;
+use super::search::tokenize;
+use crate::config::config::{default_quarantine_dir, load_config};
+use crate::models::models::Song;
+use crate::playlists::store::PlaylistStore;
+use chrono::Local;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_DURATION_TOLERANCE_MS: u64 = 2000;
+
+// Bracketed title suffixes that label the same recording differently.
+const IGNORED_TITLE_QUALIFIERS: [&str; 4] = ["remaster", "explicit", "album version", "clean"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub key: String,
+    /// Best copy first.
+    pub tracks: Vec<Song>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateResolution {
+    pub kept: String,
+    pub quarantined: Vec<MovedTrack>,
+    pub failed: Vec<String>,
+    #[serde(flatten)]
+    pub update: LibraryUpdate,
+}
+
+pub fn is_lossless(song: &Song) -> bool {
+    matches!(
+        song.codec.as_str(),
+        "FLAC" | "ALAC" | "WAV" | "AIFF" | "APE" | "WavPack"
+    )
+}
+
+/// Orders copies of the same recording best first: lossless before lossy,
+/// then higher bit depth, sample rate, bitrate and finally file size.
+pub fn compare_quality(a: &Song, b: &Song) -> Ordering {
+    is_lossless(b)
+        .cmp(&is_lossless(a))
+        .then_with(|| b.bit_depth.cmp(&a.bit_depth))
+        .then_with(|| b.sample_rate.cmp(&a.sample_rate))
+        .then_with(|| b.bitrate.cmp(&a.bitrate))
+        .then_with(|| b.file_size.cmp(&a.file_size))
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+fn normalized_title(title: &str) -> String {
+    let mut kept = String::with_capacity(title.len());
+    let mut depth = 0;
+    let mut segment = String::new();
+
+    for c in title.chars() {
+        match c {
+            '(' | '[' => {
+                if depth == 0 {
+                    segment.clear();
+                }
+                depth += 1;
+                segment.push(c);
+            }
+            ')' | ']' if depth > 0 => {
+                depth -= 1;
+                segment.push(c);
+                if depth == 0 {
+                    let lower = segment.to_lowercase();
+                    if !IGNORED_TITLE_QUALIFIERS
+                        .iter()
+                        .any(|qualifier| lower.contains(qualifier))
+                    {
+                        kept.push_str(&segment);
+                    }
+                }
+            }
+            _ if depth > 0 => segment.push(c),
+            _ => kept.push(c),
+        }
+    }
+    if depth > 0 {
+        kept.push_str(&segment);
+    }
+
+    tokenize(&kept).join(" ")
+}
+
+fn duplicate_key(song: &Song) -> Option<String> {
+    let title = normalized_title(&song.title);
+    let artist = tokenize(&song.subtitle).join(" ");
+    if title.is_empty() {
+        return None;
+    }
+    Some(format!("{}\u{1f}{}", artist, title))
+}
+
+/// Groups songs with the same normalised artist and title whose durations
+/// are within `tolerance_ms` of their neighbour, best copy first.
+pub fn find_duplicates(library: &HashMap<String, Song>, tolerance_ms: u64) -> Vec<DuplicateGroup> {
+    let mut by_key: HashMap<String, Vec<&Song>> = HashMap::new();
+    for song in library.values() {
+        if let Some(key) = duplicate_key(song) {
+            by_key.entry(key).or_default().push(song);
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (key, mut songs) in by_key {
+        if songs.len() < 2 {
+            continue;
+        }
+        songs.sort_by_key(|song| song.duration_ms);
+
+        let mut cluster: Vec<&Song> = Vec::new();
+        for song in songs {
+            if let Some(last) = cluster.last() {
+                if song.duration_ms - last.duration_ms > tolerance_ms {
+                    push_group(&mut groups, &key, std::mem::take(&mut cluster));
+                }
+            }
+            cluster.push(song);
+        }
+        push_group(&mut groups, &key, cluster);
+    }
+
+    groups.sort_by(|a, b| a.key.cmp(&b.key));
+    groups
+}
+
+/// Groups songs whose audio fingerprints match, whatever their tags say,
+/// e.g. the same rip encoded twice. Durations must still agree within
+/// `tolerance_ms`, since edits and album versions share their opening.
+pub fn find_fingerprint_duplicates(
+    library: &HashMap<String, Song>,
+    fingerprints: &[CatalogFingerprint],
+    tolerance_ms: u64,
+) -> Vec<DuplicateGroup> {
+    let indexed: Vec<&CatalogFingerprint> = fingerprints
+        .iter()
+        .filter(|entry| library.contains_key(&entry.path))
+        .collect();
+    let index = FingerprintIndex::new(
+        indexed
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.fingerprint.as_slice())),
+    );
+
+    // Union-find over paths, so chains of matches end up in one group.
+    let mut parents: HashMap<&str, &str> = HashMap::new();
+    for entry in &indexed {
+        let song = &library[&entry.path];
+        for (other, _) in index.matches(&entry.fingerprint) {
+            if other == entry.path
+                || song.duration_ms.abs_diff(library[other].duration_ms) > tolerance_ms
+            {
+                continue;
+            }
+            let (a, b) = (root(&parents, &entry.path), root(&parents, other));
+            if a != b {
+                parents.insert(a.max(b), a.min(b));
+            }
+        }
+    }
+
+    let mut clusters: HashMap<&str, Vec<&Song>> = HashMap::new();
+    for entry in &indexed {
+        clusters
+            .entry(root(&parents, &entry.path))
+            .or_default()
+            .push(&library[&entry.path]);
+    }
+
+    let mut groups = Vec::new();
+    for mut cluster in clusters.into_values() {
+        if cluster.len() < 2 {
+            continue;
+        }
+        cluster.sort_by(|a, b| compare_quality(a, b));
+        groups.push(DuplicateGroup {
+            key: format!("audio\u{1f}{}", cluster[0].path),
+            tracks: cluster.into_iter().cloned().collect(),
+        });
+    }
+    groups.sort_by(|a, b| a.key.cmp(&b.key));
+    groups
+}
+
+fn root<'a>(parents: &HashMap<&'a str, &'a str>, mut path: &'a str) -> &'a str {
+    while let Some(parent) = parents.get(path) {
+        path = parent;
+    }
+    path
+}
+
+fn push_group(groups: &mut Vec<DuplicateGroup>, key: &str, mut cluster: Vec<&Song>) {
+    if cluster.len() < 2 {
+        return;
+    }
+    cluster.sort_by(|a, b| compare_quality(a, b));
+    let duration_key = cluster[0].duration_ms / 1000;
+    groups.push(DuplicateGroup {
+        key: format!("{}\u{1f}{}", key, duration_key),
+        tracks: cluster.into_iter().cloned().collect(),
+    });
+}
+
+/// Where a quarantined file goes: its path below the library root it came
+/// from, inside a folder for this resolution run.
+fn quarantine_destination(path: &Path, roots: &[PathBuf], batch_dir: &Path) -> PathBuf {
+    let relative = roots
+        .iter()
+        .find_map(|root| path.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(path.file_name().unwrap_or(path.as_os_str())));
+
+    let mut destination = batch_dir.join(relative);
+    let mut attempt = 1;
+    while destination.exists() {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = match path.extension() {
+            Some(extension) => format!("{} ({}).{}", stem, attempt, extension.to_string_lossy()),
+            None => format!("{} ({})", stem, attempt),
+        };
+        destination.set_file_name(name);
+        attempt += 1;
+    }
+    destination
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|error| error.to_string())?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // Renames fail across filesystems; fall back to copy and delete.
+    fs::copy(from, to).map_err(|error| error.to_string())?;
+    fs::remove_file(from).map_err(|error| {
+        let _ = fs::remove_file(to);
+        error.to_string()
+    })
+}
+
+/// Keeps `keep`, moves every path in `remove` to the quarantine folder and
+/// points playlists and listening history at the kept copy. `groups` are
+/// the current duplicate groups; every path in `remove` must be in the
+/// group of `keep`, so a stale request cannot quarantine unrelated tracks.
+pub fn resolve_duplicates(
+    library: &MusicLibrary,
+    playlists: &PlaylistStore,
+    history: &ListeningHistoryStore,
+    groups: &[DuplicateGroup],
+    keep: &str,
+    remove: &[String],
+) -> Result<DuplicateResolution, String> {
+    if library.by_path(keep).is_none() {
+        return Err(format!("Track is not in the library: {}", keep));
+    }
+    let group = groups
+        .iter()
+        .find(|group| group.tracks.iter().any(|track| track.path == keep))
+        .ok_or_else(|| format!("Track has no duplicates: {}", keep))?;
+    if let Some(path) = remove.iter().find(|path| {
+        path.as_str() == keep || !group.tracks.iter().any(|track| &track.path == *path)
+    }) {
+        return Err(format!("Cannot quarantine {}", path));
+    }
+
+    let config = load_config();
+    let quarantine_dir = match config.quarantine_dir.trim() {
+        "" => default_quarantine_dir(),
+        dir => PathBuf::from(dir),
+    };
+    let batch_dir = quarantine_dir.join(Local::now().format("%Y-%m-%d %H-%M-%S").to_string());
+    let roots = library_roots(&config);
+
+    let mut quarantined = Vec::new();
+    let mut failed = Vec::new();
+    for path in remove {
+        let destination = quarantine_destination(Path::new(path), &roots, &batch_dir);
+        if let Err(error) = move_file(Path::new(path), &destination) {
+            eprintln!("Failed to quarantine {}: {}", path, error);
+            failed.push(path.clone());
+            continue;
+        }
+        quarantined.push(MovedTrack {
+            from: path.clone(),
+            to: destination.to_string_lossy().to_string(),
+        });
+    }
+
+    let removed: Vec<String> = quarantined.iter().map(|track| track.from.clone()).collect();
+    let update = library.remove_tracks(&removed);
+
+    let remapped: Vec<MovedTrack> = removed
+        .into_iter()
+        .map(|from| MovedTrack {
+            from,
+            to: keep.to_string(),
+        })
+        .collect();
+    playlists.move_track_paths(&remapped)?;
+    history.move_paths(&remapped)?;
+    library.relink_references(playlists, history);
+
+    Ok(DuplicateResolution {
+        kept: keep.to_string(),
+        quarantined,
+        failed,
+        update,
+    })
+}
@@ -0,0 +1,286 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::blocking::Client;
+use rodio::{Decoder, Source};
+use rusty_chromaprint::{Configuration, FingerprintCompressor, Fingerprinter};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Like `fpcalc`, only the start of a track is fingerprinted.
+const MAX_FINGERPRINT_SECONDS: u32 = 120;
+/// Share of matching bits from which two fingerprints are the same
+/// recording. Re-encodes score well above, different recordings around 0.6.
+pub const SAME_RECORDING_SIMILARITY: f64 = 0.9;
+/// How far, in fingerprint items of about 0.12 s, two copies may be shifted
+/// against each other, e.g. by encoder delay or trimmed silence.
+const MAX_ALIGNMENT_OFFSET: isize = 40;
+/// Values shared by more fingerprints than this, such as silence, say
+/// nothing about which tracks match.
+const MAX_INDEX_BUCKET: usize = 32;
+const MIN_SHARED_VALUES: usize = 8;
+const ACOUSTID_LOOKUP_URL: &str = "https://api.acoustid.org/v2/lookup";
+
+/// The preset AcoustID and `fpcalc` use by default.
+fn configuration() -> Configuration {
+    Configuration::preset_test2()
+}
+
+/// Decodes up to the first two minutes of the file and returns its raw
+/// Chromaprint fingerprint.
+pub fn fingerprint_file(path: &Path) -> Result<Vec<u32>, String> {
+    let file = File::open(path).map_err(|error| error.to_string())?;
+    let decoder = Decoder::new(BufReader::new(file)).map_err(|error| error.to_string())?;
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+
+    let mut printer = Fingerprinter::new(&configuration());
+    printer
+        .start(sample_rate, channels as u32)
+        .map_err(|error| error.to_string())?;
+
+    let limit = (MAX_FINGERPRINT_SECONDS * sample_rate) as usize * channels as usize;
+    let mut buffer = Vec::with_capacity(sample_rate as usize * channels as usize);
+    for sample in decoder.take(limit) {
+        buffer.push(sample);
+        if buffer.len() == buffer.capacity() {
+            printer.consume(&buffer);
+            buffer.clear();
+        }
+    }
+    printer.consume(&buffer);
+    printer.finish();
+
+    let fingerprint = printer.fingerprint().to_vec();
+    if fingerprint.is_empty() {
+        return Err("Audio is too short to fingerprint".to_string());
+    }
+    Ok(fingerprint)
+}
+
+/// The compressed, base64 form `fpcalc` prints and AcoustID expects.
+pub fn encode_fingerprint(fingerprint: &[u32]) -> String {
+    let configuration = configuration();
+    URL_SAFE_NO_PAD.encode(FingerprintCompressor::from(&configuration).compress(fingerprint))
+}
+
+/// Share of identical bits, from 0 to 1, at the best alignment of the two
+/// fingerprints.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let shorter = a.len().min(b.len());
+    let mut best = 0.0;
+    for offset in -MAX_ALIGNMENT_OFFSET..=MAX_ALIGNMENT_OFFSET {
+        let (a, b) = if offset < 0 {
+            (a, b.get(offset.unsigned_abs()..).unwrap_or_default())
+        } else {
+            (a.get(offset as usize..).unwrap_or_default(), b)
+        };
+        let overlap = a.len().min(b.len());
+        // Too short an overlap matches by chance.
+        if overlap == 0 || overlap * 2 < shorter {
+            continue;
+        }
+        let differing: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+        let score = 1.0 - differing as f64 / (overlap * 32) as f64;
+        if score > best {
+            best = score;
+        }
+    }
+    best
+}
+
+/// Finds fingerprints of the same recording without comparing every pair:
+/// only tracks that share several exact fingerprint values are compared.
+pub struct FingerprintIndex<'a> {
+    fingerprints: Vec<(&'a str, &'a [u32])>,
+    by_value: HashMap<u32, Vec<usize>>,
+}
+
+impl<'a> FingerprintIndex<'a> {
+    pub fn new(fingerprints: impl IntoIterator<Item = (&'a str, &'a [u32])>) -> Self {
+        let fingerprints: Vec<(&str, &[u32])> = fingerprints.into_iter().collect();
+        let mut by_value: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, (_, fingerprint)) in fingerprints.iter().enumerate() {
+            for value in distinct_values(fingerprint) {
+                by_value.entry(value).or_default().push(index);
+            }
+        }
+        by_value.retain(|_, entries| entries.len() <= MAX_INDEX_BUCKET);
+        Self {
+            fingerprints,
+            by_value,
+        }
+    }
+
+    /// Keys of indexed fingerprints of the same recording, most similar
+    /// first.
+    pub fn matches(&self, fingerprint: &[u32]) -> Vec<(&'a str, f64)> {
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for value in distinct_values(fingerprint) {
+            for index in self.by_value.get(&value).into_iter().flatten() {
+                *shared.entry(*index).or_default() += 1;
+            }
+        }
+
+        let mut matches: Vec<(&str, f64)> = shared
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_SHARED_VALUES)
+            .map(|(index, _)| {
+                let (key, other) = self.fingerprints[index];
+                (key, similarity(fingerprint, other))
+            })
+            .filter(|(_, score)| *score >= SAME_RECORDING_SIMILARITY)
+            .collect();
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        matches
+    }
+}
+
+fn distinct_values(fingerprint: &[u32]) -> Vec<u32> {
+    let mut values: Vec<u32> = fingerprint
+        .iter()
+        .copied()
+        .filter(|value| *value != 0)
+        .collect();
+    values.sort_unstable();
+    values.dedup();
+    values
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AcoustIdMatch {
+    /// AcoustID's confidence, from 0 to 1.
+    pub score: f64,
+    pub acoustid: String,
+    /// MusicBrainz recording id.
+    pub recording_id: Option<String>,
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub duration_seconds: Option<u64>,
+}
+
+/// Asks AcoustID which MusicBrainz recordings the fingerprint belongs to.
+/// `client_key` is an AcoustID application key.
+pub fn lookup_acoustid(
+    client: &Client,
+    client_key: &str,
+    fingerprint: &[u32],
+    duration_seconds: u64,
+) -> Result<Vec<AcoustIdMatch>, String> {
+    let response: Value = client
+        .post(ACOUSTID_LOOKUP_URL)
+        .form(&[
+            ("client", client_key.to_string()),
+            ("meta", "recordings".to_string()),
+            ("duration", duration_seconds.to_string()),
+            ("fingerprint", encode_fingerprint(fingerprint)),
+        ])
+        .send()
+        .and_then(|response| response.json())
+        .map_err(|error| error.to_string())?;
+
+    if response["status"] != "ok" {
+        let message = response["error"]["message"]
+            .as_str()
+            .unwrap_or("AcoustID lookup failed");
+        return Err(message.to_string());
+    }
+
+    let mut matches = Vec::new();
+    for result in response["results"].as_array().into_iter().flatten() {
+        let score = result["score"].as_f64().unwrap_or(0.0);
+        let acoustid = result["id"].as_str().unwrap_or_default().to_string();
+        let recordings = result["recordings"].as_array().map(Vec::as_slice);
+
+        // Fingerprints nobody linked to MusicBrainz yet come back bare.
+        if recordings.is_none_or(|recordings| recordings.is_empty()) {
+            matches.push(AcoustIdMatch {
+                score,
+                acoustid,
+                recording_id: None,
+                title: None,
+                artists: Vec::new(),
+                duration_seconds: None,
+            });
+            continue;
+        }
+        for recording in recordings.into_iter().flatten() {
+            matches.push(AcoustIdMatch {
+                score,
+                acoustid: acoustid.clone(),
+                recording_id: recording["id"].as_str().map(str::to_string),
+                title: recording["title"].as_str().map(str::to_string),
+                artists: recording["artists"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|artist| artist["name"].as_str().map(str::to_string))
+                    .collect(),
+                duration_seconds: recording["duration"].as_u64(),
+            });
+        }
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic stand-in for a real fingerprint.
+    fn fingerprint(seed: u32, length: usize) -> Vec<u32> {
+        let mut state = seed;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encodes_like_fpcalc() {
+        // Algorithm 1, the one fpcalc uses and the reason every fpcalc
+        // fingerprint starts with "AQAA", then a 24-bit item count and the
+        // 3-bit packed positions of the bits that changed.
+        assert_eq!(encode_fingerprint(&[1]), "AQAAAQE");
+        assert_eq!(encode_fingerprint(&[1, 0]), "AQAAAkEA");
+        // Positions of 7 and above spill into the exceptional bits.
+        assert_eq!(encode_fingerprint(&[1 << 8]), "AQAAAQcC");
+    }
+
+    #[test]
+    fn similarity_finds_shifted_copies() {
+        let original = fingerprint(1, 200);
+        assert_eq!(similarity(&original, &original), 1.0);
+        assert_eq!(similarity(&original[5..], &original), 1.0);
+
+        let inverted: Vec<u32> = original.iter().map(|value| !value).collect();
+        assert!(similarity(&original, &inverted) < SAME_RECORDING_SIMILARITY);
+        let unrelated = fingerprint(2, 200);
+        assert!(similarity(&original, &unrelated) < SAME_RECORDING_SIMILARITY);
+    }
+
+    #[test]
+    fn index_matches_same_recording_only() {
+        let original = fingerprint(1, 200);
+        let mut reencoded = original[3..].to_vec();
+        for value in reencoded.iter_mut().step_by(10) {
+            *value ^= 1;
+        }
+        let unrelated = fingerprint(2, 200);
+
+        let index = FingerprintIndex::new([
+            ("reencoded", reencoded.as_slice()),
+            ("unrelated", unrelated.as_slice()),
+        ]);
+        let matches = index.matches(&original);
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, "reencoded");
+        assert!(matches[0].1 > 0.99);
+    }
+}
@@ -1,3 +1,4 @@
+use super::library::MovedTrack;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -29,12 +30,40 @@ pub enum ContinueListeningItem {
     },
 }
 
+#[derive(Debug, Clone)]
+pub struct HistoryTrackRecord {
+    pub track_path: String,
+    pub track_id: Option<String>,
+    pub play_count: usize,
+    pub last_played_at: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ListeningEvent {
     path: String,
+    #[serde(default)]
+    track_id: Option<String>,
     played_at: i64,
     #[serde(default)]
     source: Option<ListeningSource>,
+    /// Plays carried over from another player are stored as one event per
+    /// track standing for all of them. Such events are never trimmed.
+    #[serde(default)]
+    imported_plays: Option<u32>,
+}
+
+impl ListeningEvent {
+    fn plays(&self) -> usize {
+        self.imported_plays.map_or(1, |plays| plays as usize)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedPlays {
+    pub path: String,
+    pub track_id: Option<String>,
+    pub play_count: u32,
+    pub last_played_at: i64,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -57,7 +86,12 @@ impl ListeningHistoryStore {
         }
     }
 
-    pub fn record_play(&self, path: &str, source: Option<ListeningSource>) -> Result<(), String> {
+    pub fn record_play(
+        &self,
+        path: &str,
+        track_id: Option<String>,
+        source: Option<ListeningSource>,
+    ) -> Result<(), String> {
         let clean_path = path.trim();
         if clean_path.is_empty() {
             return Ok(());
@@ -70,18 +104,148 @@ impl ListeningHistoryStore {
 
         data.events.push(ListeningEvent {
             path: clean_path.to_string(),
+            track_id,
             played_at: Utc::now().timestamp(),
             source: source.and_then(normalize_source),
+            imported_plays: None,
+        });
+
+        let played = data
+            .events
+            .iter()
+            .filter(|event| event.imported_plays.is_none())
+            .count();
+        let mut overflow = played.saturating_sub(MAX_HISTORY_EVENTS);
+        data.events.retain(|event| {
+            if overflow > 0 && event.imported_plays.is_none() {
+                overflow -= 1;
+                return false;
+            }
+            true
         });
 
-        if data.events.len() > MAX_HISTORY_EVENTS {
-            let overflow = data.events.len() - MAX_HISTORY_EVENTS;
-            data.events.drain(0..overflow);
+        persist_history(&self.file_path, &data)
+    }
+
+    /// Replaces earlier imported plays of the same paths, keeping events in
+    /// chronological order.
+    pub fn import_plays(&self, plays: &[ImportedPlays]) -> Result<(), String> {
+        if plays.is_empty() {
+            return Ok(());
         }
 
+        let mut data = self
+            .data
+            .lock()
+            .map_err(|_| "Listening history mutex is poisoned".to_string())?;
+
+        let paths: HashSet<&str> = plays.iter().map(|play| play.path.as_str()).collect();
+        data.events
+            .retain(|event| event.imported_plays.is_none() || !paths.contains(event.path.as_str()));
+        data.events.extend(plays.iter().map(|play| ListeningEvent {
+            path: play.path.clone(),
+            track_id: play.track_id.clone(),
+            played_at: play.last_played_at,
+            source: None,
+            imported_plays: Some(play.play_count),
+        }));
+        data.events.sort_by_key(|event| event.played_at);
+
         persist_history(&self.file_path, &data)
     }
 
+    pub fn move_paths(&self, moved: &[MovedTrack]) -> Result<(), String> {
+        self.move_paths_then(moved, || Ok(()))
+    }
+
+    /// Moves events like `move_paths`, then runs `commit`. If `commit`
+    /// fails, the previous history is put back.
+    pub fn move_paths_then<F>(&self, moved: &[MovedTrack], commit: F) -> Result<(), String>
+    where
+        F: FnOnce() -> Result<(), String>,
+    {
+        if moved.is_empty() {
+            return commit();
+        }
+
+        let mut data = self
+            .data
+            .lock()
+            .map_err(|_| "Listening history mutex is poisoned".to_string())?;
+
+        let renames: HashMap<&str, &str> = moved
+            .iter()
+            .map(|track| (track.from.as_str(), track.to.as_str()))
+            .collect();
+        let previous = data.events.clone();
+        let mut changed = false;
+
+        for event in data.events.iter_mut() {
+            if let Some(new_path) = renames.get(event.path.as_str()) {
+                event.path = new_path.to_string();
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return commit();
+        }
+
+        if let Err(error) = persist_history(&self.file_path, &data) {
+            data.events = previous;
+            return Err(error);
+        }
+        if let Err(error) = commit() {
+            data.events = previous;
+            persist_history(&self.file_path, &data)?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Same as `PlaylistStore::relink_tracks`, for listening events.
+    pub fn relink_tracks(
+        &self,
+        ids_by_path: &HashMap<String, String>,
+        paths_by_id: &HashMap<String, String>,
+    ) -> Result<Vec<MovedTrack>, String> {
+        let mut data = self
+            .data
+            .lock()
+            .map_err(|_| "Listening history mutex is poisoned".to_string())?;
+
+        let mut moved: Vec<MovedTrack> = Vec::new();
+        let mut changed = false;
+
+        for event in data.events.iter_mut() {
+            if let Some(current_id) = ids_by_path.get(&event.path) {
+                if event.track_id.as_ref() != Some(current_id) {
+                    event.track_id = Some(current_id.clone());
+                    changed = true;
+                }
+                continue;
+            }
+
+            let Some(new_path) = event.track_id.as_ref().and_then(|id| paths_by_id.get(id)) else {
+                continue;
+            };
+
+            if !moved.iter().any(|track| track.from == event.path) {
+                moved.push(MovedTrack {
+                    from: event.path.clone(),
+                    to: new_path.clone(),
+                });
+            }
+            event.path = new_path.clone();
+            changed = true;
+        }
+
+        if changed {
+            persist_history(&self.file_path, &data)?;
+        }
+        Ok(moved)
+    }
+
     pub fn recent_paths(&self, limit: usize) -> Vec<String> {
         if limit == 0 {
             return Vec::new();
@@ -172,6 +336,45 @@ impl ListeningHistoryStore {
         result
     }
 
+    /// Every path in the history with its play count and last play.
+    pub fn track_references(&self) -> Vec<HistoryTrackRecord> {
+        let data = match self.data.lock() {
+            Ok(data) => data,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut by_path: HashMap<&str, HistoryTrackRecord> = HashMap::new();
+        for event in &data.events {
+            let record = by_path
+                .entry(event.path.as_str())
+                .or_insert_with(|| HistoryTrackRecord {
+                    track_path: event.path.clone(),
+                    track_id: None,
+                    play_count: 0,
+                    last_played_at: event.played_at,
+                });
+            record.play_count += event.plays();
+            record.last_played_at = record.last_played_at.max(event.played_at);
+            if event.track_id.is_some() {
+                record.track_id = event.track_id.clone();
+            }
+        }
+        by_path.into_values().collect()
+    }
+
+    pub fn play_counts(&self) -> HashMap<String, usize> {
+        let data = match self.data.lock() {
+            Ok(data) => data,
+            Err(_) => return HashMap::new(),
+        };
+
+        let mut counts = HashMap::new();
+        for event in &data.events {
+            *counts.entry(event.path.clone()).or_insert(0) += event.plays();
+        }
+        counts
+    }
+
     pub fn most_played_week_paths(&self, limit: usize) -> Vec<String> {
         if limit == 0 {
             return Vec::new();
@@ -186,7 +389,8 @@ impl ListeningHistoryStore {
         let mut counts: HashMap<String, (usize, i64)> = HashMap::new();
 
         for event in &data.events {
-            if event.played_at < week_cutoff {
+            // Imported plays are spread over unknown dates.
+            if event.played_at < week_cutoff || event.imported_plays.is_some() {
                 continue;
             }
             let entry = counts.entry(event.path.clone()).or_insert((0, 0));
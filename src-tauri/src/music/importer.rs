@@ -0,0 +1,645 @@
+use super::history::{ImportedPlays, ListeningHistoryStore};
+use super::library::MusicLibrary;
+use super::search::tokenize;
+use crate::models::models::{Song, TrackStats};
+use crate::playlists::store::PlaylistStore;
+use chrono::Utc;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Exported durations are rounded differently by every player.
+const DURATION_TOLERANCE_MS: u64 = 3000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSource {
+    /// `Library.xml` exported by iTunes or Music.app.
+    Itunes,
+    /// `rhythmdb.xml`, with `playlists.xml` next to it.
+    Rhythmbox,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMethod {
+    Path,
+    Tags,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmatchedTrack {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistImport {
+    pub name: String,
+    pub tracks: usize,
+    pub matched: usize,
+    /// Set once the playlist was written.
+    pub slug: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub source: ImportSource,
+    pub dry_run: bool,
+    pub tracks: usize,
+    pub matched_by_path: usize,
+    pub matched_by_tags: usize,
+    pub unmatched: Vec<UnmatchedTrack>,
+    pub playlists: Vec<PlaylistImport>,
+    pub ratings: usize,
+    pub added_dates: usize,
+    pub plays: u64,
+}
+
+#[derive(Debug, Default)]
+struct ExternalTrack {
+    location: Option<PathBuf>,
+    title: String,
+    artist: String,
+    album: String,
+    duration_ms: Option<u64>,
+    /// 1 to 5 stars.
+    rating: Option<u8>,
+    play_count: u32,
+    last_played_at: Option<i64>,
+    added_at: Option<i64>,
+}
+
+#[derive(Debug)]
+struct ExternalPlaylist {
+    name: String,
+    /// Indices into `ExternalLibrary::tracks`.
+    tracks: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+struct ExternalLibrary {
+    tracks: Vec<ExternalTrack>,
+    playlists: Vec<ExternalPlaylist>,
+}
+
+/// Reads another player's library, matches its tracks against the indexed
+/// ones and, unless `dry_run` is set, carries over playlists, ratings, date
+/// added and play counts.
+pub fn import_library(
+    library: &MusicLibrary,
+    playlists: &PlaylistStore,
+    history: &ListeningHistoryStore,
+    source: ImportSource,
+    path: &Path,
+    playlists_path: Option<&Path>,
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    let external = match source {
+        ImportSource::Itunes => read_itunes(path)?,
+        ImportSource::Rhythmbox => {
+            let playlists_path = playlists_path
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| path.with_file_name("playlists.xml"));
+            read_rhythmbox(path, &playlists_path)?
+        }
+    };
+
+    // Path and track id of the indexed song each external track matched.
+    let matches: Vec<Option<(String, String, MatchMethod)>> = {
+        let songs = library.library.lock().unwrap();
+        let matcher = Matcher::new(&songs);
+        external
+            .tracks
+            .iter()
+            .map(|track| {
+                matcher
+                    .find(track)
+                    .map(|(song, method)| (song.path.clone(), song.track_id.clone(), method))
+            })
+            .collect()
+    };
+
+    let mut report = ImportReport {
+        source,
+        dry_run,
+        tracks: external.tracks.len(),
+        matched_by_path: 0,
+        matched_by_tags: 0,
+        unmatched: Vec::new(),
+        playlists: Vec::new(),
+        ratings: 0,
+        added_dates: 0,
+        plays: 0,
+    };
+    let mut stats: HashMap<String, TrackStats> = HashMap::new();
+    let mut plays = Vec::new();
+
+    for (track, matched) in external.tracks.iter().zip(&matches) {
+        let Some((path, track_id, method)) = matched else {
+            report.unmatched.push(UnmatchedTrack {
+                title: track.title.clone(),
+                artist: track.artist.clone(),
+                album: track.album.clone(),
+                location: track
+                    .location
+                    .as_ref()
+                    .map(|location| location.to_string_lossy().to_string()),
+            });
+            continue;
+        };
+        match method {
+            MatchMethod::Path => report.matched_by_path += 1,
+            MatchMethod::Tags => report.matched_by_tags += 1,
+        }
+
+        if track.rating.is_some() || track.added_at.is_some() {
+            report.ratings += track.rating.is_some() as usize;
+            report.added_dates += track.added_at.is_some() as usize;
+            stats.insert(
+                track_id.clone(),
+                TrackStats {
+                    rating: track.rating,
+                    added_at: track.added_at,
+                },
+            );
+        }
+        if track.play_count > 0 {
+            report.plays += track.play_count as u64;
+            plays.push(ImportedPlays {
+                path: path.clone(),
+                track_id: Some(track_id.clone()),
+                play_count: track.play_count,
+                last_played_at: track
+                    .last_played_at
+                    .or(track.added_at)
+                    .unwrap_or_else(|| Utc::now().timestamp()),
+            });
+        }
+    }
+
+    for playlist in &external.playlists {
+        let tracks: Vec<(String, String)> = playlist
+            .tracks
+            .iter()
+            .filter_map(|index| matches.get(*index)?.as_ref())
+            .map(|(path, track_id, _)| (path.clone(), track_id.clone()))
+            .collect();
+        let slug = if dry_run || tracks.is_empty() {
+            None
+        } else {
+            Some(playlists.add_tracks_to_named(&playlist.name, &tracks)?)
+        };
+        report.playlists.push(PlaylistImport {
+            name: playlist.name.clone(),
+            tracks: playlist.tracks.len(),
+            matched: tracks.len(),
+            slug,
+        });
+    }
+
+    if !dry_run {
+        playlists.merge_track_stats(&stats)?;
+        library.set_track_stats(playlists.track_stats()?);
+        history.import_plays(&plays)?;
+    }
+    Ok(report)
+}
+
+/// Finds indexed songs by exact path first, then by title, artist and
+/// album with a close enough duration.
+struct Matcher<'a> {
+    by_path: &'a HashMap<String, Song>,
+    by_tags: HashMap<(String, String), Vec<&'a Song>>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(library: &'a HashMap<String, Song>) -> Self {
+        let mut by_tags: HashMap<(String, String), Vec<&Song>> = HashMap::new();
+        for song in library.values() {
+            let titles = [normalized(&song.title)];
+            let artists = song
+                .artists
+                .iter()
+                .chain([&song.subtitle])
+                .map(|artist| normalized(artist));
+            for artist in artists {
+                let entry = by_tags.entry((titles[0].clone(), artist)).or_default();
+                if !entry.iter().any(|known| known.path == song.path) {
+                    entry.push(song);
+                }
+            }
+        }
+        Self {
+            by_path: library,
+            by_tags,
+        }
+    }
+
+    fn find(&self, track: &ExternalTrack) -> Option<(&'a Song, MatchMethod)> {
+        if let Some(song) = track
+            .location
+            .as_ref()
+            .and_then(|location| self.by_path.get(location.to_string_lossy().as_ref()))
+        {
+            return Some((song, MatchMethod::Path));
+        }
+
+        let key = (normalized(&track.title), normalized(&track.artist));
+        let album = normalized(&track.album);
+        self.by_tags
+            .get(&key)?
+            .iter()
+            .filter(|song| {
+                track.duration_ms.is_none_or(|duration| {
+                    song.duration_ms.abs_diff(duration) <= DURATION_TOLERANCE_MS
+                })
+            })
+            .min_by_key(|song| {
+                let other_album = normalized(&song.album) != album;
+                let duration_gap = track
+                    .duration_ms
+                    .map_or(0, |duration| song.duration_ms.abs_diff(duration));
+                (other_album, duration_gap)
+            })
+            .map(|song| (*song, MatchMethod::Tags))
+    }
+}
+
+fn normalized(text: &str) -> String {
+    tokenize(text).join(" ")
+}
+
+fn read_itunes(path: &Path) -> Result<ExternalLibrary, String> {
+    let root = plist::Value::from_file(path).map_err(|error| error.to_string())?;
+    let root = root.as_dictionary().ok_or("Not an iTunes library file")?;
+    let tracks = root
+        .get("Tracks")
+        .and_then(plist::Value::as_dictionary)
+        .ok_or("Not an iTunes library file")?;
+
+    let mut library = ExternalLibrary::default();
+    let mut indices: HashMap<u64, usize> = HashMap::new();
+
+    for (id, track) in tracks {
+        let Some(track) = track.as_dictionary() else {
+            continue;
+        };
+        let text = |key: &str| {
+            track
+                .get(key)
+                .and_then(plist::Value::as_string)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let number = |key: &str| track.get(key).and_then(plist::Value::as_unsigned_integer);
+        let date = |key: &str| {
+            track
+                .get(key)
+                .and_then(plist::Value::as_date)
+                .and_then(|date| unix_seconds(date.into()))
+        };
+        // Ratings inherited from the album are computed, not set by the user.
+        let computed_rating = track
+            .get("Rating Computed")
+            .and_then(plist::Value::as_boolean)
+            .unwrap_or(false);
+
+        let track_id = number("Track ID").or_else(|| id.parse().ok());
+        let index = library.tracks.len();
+        library.tracks.push(ExternalTrack {
+            location: track
+                .get("Location")
+                .and_then(plist::Value::as_string)
+                .and_then(file_url_to_path),
+            title: text("Name"),
+            artist: text("Artist"),
+            album: text("Album"),
+            duration_ms: number("Total Time"),
+            rating: number("Rating")
+                .filter(|_| !computed_rating)
+                .and_then(|rating| stars((rating as f64 / 20.0).round())),
+            play_count: number("Play Count").unwrap_or(0) as u32,
+            last_played_at: date("Play Date UTC"),
+            added_at: date("Date Added"),
+        });
+        if let Some(track_id) = track_id {
+            indices.insert(track_id, index);
+        }
+    }
+
+    let playlists = root
+        .get("Playlists")
+        .and_then(plist::Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for playlist in playlists {
+        let Some(playlist) = playlist.as_dictionary() else {
+            continue;
+        };
+        let flag = |key: &str| {
+            playlist
+                .get(key)
+                .and_then(plist::Value::as_boolean)
+                .unwrap_or(false)
+        };
+        // The whole library, the built-in Music/Movies/Podcasts lists and
+        // folders are not playlists of their own.
+        if flag("Master") || flag("Folder") || playlist.contains_key("Distinguished Kind") {
+            continue;
+        }
+        let Some(name) = playlist.get("Name").and_then(plist::Value::as_string) else {
+            continue;
+        };
+        let tracks = playlist
+            .get("Playlist Items")
+            .and_then(plist::Value::as_array)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        let id = item
+                            .as_dictionary()?
+                            .get("Track ID")?
+                            .as_unsigned_integer()?;
+                        indices.get(&id).copied()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        library.playlists.push(ExternalPlaylist {
+            name: name.to_string(),
+            tracks,
+        });
+    }
+
+    Ok(library)
+}
+
+fn read_rhythmbox(path: &Path, playlists_path: &Path) -> Result<ExternalLibrary, String> {
+    let mut library = ExternalLibrary::default();
+    let mut indices: HashMap<PathBuf, usize> = HashMap::new();
+
+    // Only <entry type="song"> elements; podcasts and radio are skipped.
+    read_xml(path, "entry", "song", |fields, _| {
+        let text = |key: &str| fields.get(key).cloned().unwrap_or_default();
+        let number = |key: &str| {
+            fields
+                .get(key)
+                .and_then(|value| value.trim().parse::<f64>().ok())
+        };
+        let seconds = |key: &str| {
+            number(key)
+                .map(|value| value as i64)
+                .filter(|value| *value > 0)
+        };
+
+        let location = fields.get("location").and_then(|url| file_url_to_path(url));
+        if let Some(location) = &location {
+            indices.insert(location.clone(), library.tracks.len());
+        }
+        library.tracks.push(ExternalTrack {
+            location,
+            title: text("title"),
+            artist: text("artist"),
+            album: text("album"),
+            duration_ms: number("duration").map(|seconds| (seconds * 1000.0) as u64),
+            rating: number("rating").and_then(|rating| stars(rating.round())),
+            play_count: number("play-count").unwrap_or(0.0) as u32,
+            last_played_at: seconds("last-played"),
+            added_at: seconds("first-seen"),
+        });
+    })?;
+
+    // Automatic playlists are saved searches rather than track lists.
+    if playlists_path.is_file() {
+        read_xml(playlists_path, "playlist", "static", |_, playlist| {
+            let Some(name) = playlist.name.filter(|name| !name.is_empty()) else {
+                return;
+            };
+            library.playlists.push(ExternalPlaylist {
+                name,
+                tracks: playlist
+                    .locations
+                    .iter()
+                    .filter_map(|url| indices.get(&file_url_to_path(url)?).copied())
+                    .collect(),
+            });
+        })?;
+    }
+
+    Ok(library)
+}
+
+#[derive(Default)]
+struct XmlPlaylist {
+    name: Option<String>,
+    locations: Vec<String>,
+}
+
+/// Calls `on_element` for every `<element type="kind">` with the text of its
+/// child elements. Repeated `<location>` children, as in Rhythmbox
+/// playlists, are collected separately.
+fn read_xml<F>(path: &Path, element: &str, kind: &str, mut on_element: F) -> Result<(), String>
+where
+    F: FnMut(&HashMap<String, String>, XmlPlaylist),
+{
+    let mut reader = Reader::from_file(path).map_err(|error| error.to_string())?;
+    let mut buffer = Vec::new();
+    let mut inside = false;
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut playlist = XmlPlaylist::default();
+    let mut field: Option<String> = None;
+    let mut value = String::new();
+
+    loop {
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|error| error.to_string())?
+        {
+            Event::Start(start) if start.name().as_ref() == element.as_bytes() => {
+                let attribute = |name: &str| -> Option<String> {
+                    let value = start.try_get_attribute(name).ok()??;
+                    Some(value.unescape_value().ok()?.to_string())
+                };
+                inside = attribute("type").as_deref() == Some(kind);
+                fields.clear();
+                playlist = XmlPlaylist {
+                    name: attribute("name"),
+                    locations: Vec::new(),
+                };
+            }
+            Event::Start(start) if inside => {
+                field = Some(String::from_utf8_lossy(start.name().as_ref()).to_string());
+                value.clear();
+            }
+            Event::Text(text) if field.is_some() => {
+                value.push_str(&text.decode().map_err(|error| error.to_string())?);
+            }
+            Event::GeneralRef(reference) if field.is_some() => {
+                let name = reference.decode().map_err(|error| error.to_string())?;
+                let entity = format!("&{};", name);
+                let resolved =
+                    quick_xml::escape::unescape(&entity).map_err(|error| error.to_string())?;
+                value.push_str(&resolved);
+            }
+            Event::End(end) if end.name().as_ref() == element.as_bytes() => {
+                if inside {
+                    on_element(&fields, std::mem::take(&mut playlist));
+                }
+                inside = false;
+            }
+            Event::End(_) => {
+                if let Some(name) = field.take() {
+                    if name == "location" {
+                        playlist.locations.push(value.clone());
+                    }
+                    fields.insert(name, value.clone());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    Ok(())
+}
+
+fn stars(rating: f64) -> Option<u8> {
+    (1.0..=5.0).contains(&rating).then_some(rating as u8)
+}
+
+fn unix_seconds(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs() as i64)
+}
+
+/// `file:///Users/me/Music/a%20b.mp3`, `file://localhost/...` and Windows'
+/// `file://localhost/C:/...` to a local path.
+fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+
+    let bytes = rest.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    let path = String::from_utf8(decoded).ok()?;
+
+    // "/C:/Music" is a drive path on Windows.
+    let is_drive_path = path.as_bytes().get(2) == Some(&b':')
+        && path.as_bytes().get(1).is_some_and(u8::is_ascii_alphabetic);
+    if cfg!(windows) && is_drive_path {
+        return Some(PathBuf::from(path[1..].replace('/', "\\")));
+    }
+    Some(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rift-importer-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn file_urls() {
+        assert_eq!(
+            file_url_to_path("file:///home/me/Music/a%20b.mp3"),
+            Some(PathBuf::from("/home/me/Music/a b.mp3"))
+        );
+        assert_eq!(
+            file_url_to_path("file://localhost/Users/me/Caf%C3%A9.m4a"),
+            Some(PathBuf::from("/Users/me/Café.m4a"))
+        );
+        // A stray percent sign is kept as it is.
+        assert_eq!(
+            file_url_to_path("file:///music/100%.mp3"),
+            Some(PathBuf::from("/music/100%.mp3"))
+        );
+        assert_eq!(file_url_to_path("http://example.com/a.mp3"), None);
+        assert_eq!(file_url_to_path("file:///bad%FF.mp3"), None);
+    }
+
+    #[test]
+    fn reads_matching_xml_elements() {
+        let path = write_temp(
+            "rhythmdb.xml",
+            r#"<?xml version="1.0"?>
+<rhythmdb>
+  <entry type="song">
+    <title>Rock &amp; Roll</title>
+    <artist>Led Zeppelin</artist>
+    <play-count>3</play-count>
+  </entry>
+  <entry type="iradio">
+    <title>Radio</title>
+  </entry>
+  <playlist name="Mix" type="static">
+    <location>file:///a.mp3</location>
+    <location>file:///b.mp3</location>
+  </playlist>
+</rhythmdb>"#,
+        );
+
+        let mut songs = Vec::new();
+        read_xml(&path, "entry", "song", |fields, _| {
+            songs.push(fields.clone())
+        })
+        .unwrap();
+        let mut playlists = Vec::new();
+        read_xml(&path, "playlist", "static", |_, playlist| {
+            playlists.push(playlist)
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0]["title"], "Rock & Roll");
+        assert_eq!(songs[0]["play-count"], "3");
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].name.as_deref(), Some("Mix"));
+        assert_eq!(playlists[0].locations, ["file:///a.mp3", "file:///b.mp3"]);
+    }
+
+    #[test]
+    fn broken_xml_is_an_error() {
+        let path = write_temp("broken.xml", "<rhythmdb><entry type=\"song\"></rhythmdb>");
+        let result = read_xml(&path, "entry", "song", |_, _| {});
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn star_ratings() {
+        assert_eq!(stars(0.0), None);
+        assert_eq!(stars(3.0), Some(3));
+        assert_eq!(stars(6.0), None);
+    }
+}
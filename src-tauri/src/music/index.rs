@@ -0,0 +1,160 @@
+use super::search::fold_text;
+use crate::models::models::Song;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// In-memory SQLite FTS5 index over title, artist and album. The library is
+/// the source of truth; this only answers "which paths match" quickly.
+#[derive(Debug)]
+pub struct SearchIndex {
+    state: Mutex<IndexState>,
+}
+
+#[derive(Debug)]
+struct IndexState {
+    connection: Connection,
+    rowids: HashMap<String, i64>,
+    terms: Option<Arc<[String]>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        let connection = Connection::open_in_memory().expect("Failed to open search index");
+        // Text is folded before it is stored, so the default tokenizer only
+        // has to split on the spaces `fold_text` leaves behind.
+        connection
+            .execute_batch(
+                "CREATE VIRTUAL TABLE tracks USING fts5(
+                    path UNINDEXED,
+                    title,
+                    artist,
+                    album,
+                    prefix = '1 2 3'
+                 );
+                 CREATE VIRTUAL TABLE track_terms USING fts5vocab(tracks, 'row');",
+            )
+            .expect("Failed to initialize search index");
+
+        Self {
+            state: Mutex::new(IndexState {
+                connection,
+                rowids: HashMap::new(),
+                terms: None,
+            }),
+        }
+    }
+
+    /// Adds or replaces the given songs and drops `removed` paths in a single
+    /// transaction.
+    pub fn update<'a>(
+        &self,
+        songs: impl IntoIterator<Item = &'a Song>,
+        removed: impl IntoIterator<Item = &'a str>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if let Err(error) = state.update(songs, removed) {
+            eprintln!("Failed to update search index: {}", error);
+        }
+    }
+
+    /// Paths matching the FTS5 `expression`, best bm25 rank first. Title
+    /// matches weigh more than artist matches, which weigh more than album.
+    pub fn search(&self, expression: &str, limit: Option<usize>) -> Result<Vec<String>, String> {
+        let state = self.state.lock().unwrap();
+        let mut statement = state
+            .connection
+            .prepare_cached(
+                "SELECT path FROM tracks
+                 WHERE tracks MATCH ?1
+                 ORDER BY bm25(tracks, 0.0, 3.0, 2.0, 1.0)
+                 LIMIT ?2",
+            )
+            .map_err(|error| error.to_string())?;
+
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let rows = statement
+            .query_map(params![expression, limit], |row| row.get::<_, String>(0))
+            .map_err(|error| error.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|error| error.to_string())
+    }
+
+    /// Every distinct token in the index, used to correct typos. Cached
+    /// until the next update since reading it walks the whole index.
+    pub fn terms(&self) -> Result<Arc<[String]>, String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(terms) = &state.terms {
+            return Ok(terms.clone());
+        }
+
+        let terms: Arc<[String]> = {
+            let mut statement = state
+                .connection
+                .prepare_cached("SELECT term FROM track_terms")
+                .map_err(|error| error.to_string())?;
+            let rows = statement
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|error| error.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|error| error.to_string())?
+                .into()
+        };
+        state.terms = Some(terms.clone());
+        Ok(terms)
+    }
+}
+
+impl IndexState {
+    fn update<'a>(
+        &mut self,
+        songs: impl IntoIterator<Item = &'a Song>,
+        removed: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), String> {
+        self.terms = None;
+        let transaction = self
+            .connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+
+        {
+            let mut delete = transaction
+                .prepare_cached("DELETE FROM tracks WHERE rowid = ?1")
+                .map_err(|error| error.to_string())?;
+            let mut insert = transaction
+                .prepare_cached(
+                    "INSERT INTO tracks (path, title, artist, album) VALUES (?1, ?2, ?3, ?4)",
+                )
+                .map_err(|error| error.to_string())?;
+
+            for path in removed {
+                if let Some(rowid) = self.rowids.remove(path) {
+                    delete
+                        .execute(params![rowid])
+                        .map_err(|error| error.to_string())?;
+                }
+            }
+
+            for song in songs {
+                if let Some(rowid) = self.rowids.remove(&song.path) {
+                    delete
+                        .execute(params![rowid])
+                        .map_err(|error| error.to_string())?;
+                }
+                insert
+                    .execute(params![
+                        song.path,
+                        fold_text(&song.title),
+                        fold_text(&song.subtitle),
+                        fold_text(&song.album),
+                    ])
+                    .map_err(|error| error.to_string())?;
+                self.rowids
+                    .insert(song.path.clone(), transaction.last_insert_rowid());
+            }
+        }
+
+        transaction.commit().map_err(|error| error.to_string())
+    }
+}
@@ -1,69 +1,561 @@
-use super::scanner::index_music_files;
-use crate::models::models::Song;
-use std::collections::HashMap;
+use super::browse::{
+    artist_key, find_album, find_artist, list_albums, list_artists, AlbumDetail, AlbumSummary,
+    ArtistDetail, ArtistSummary,
+};
+use super::history::ListeningHistoryStore;
+use super::index::SearchIndex;
+use super::listing::{list_tracks, TrackListOptions, TrackPage};
+use super::metadata::read_audio_metadata;
+use super::query::{Query, QueryError};
+use super::scanner::{
+    cleanup_unused_covers, file_stamp, is_audio_file, scan_directory, scan_music_files,
+    used_cover_hashes, FileStamp, ScanIssueKind, ScanOptions, ScanReport,
+};
+use super::search::{matching_songs, search_songs};
+use crate::models::models::{Song, TrackStats};
+use crate::playlists::store::PlaylistStore;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const MAX_REINDEX_WORKERS: usize = 8;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+type ParsedFile = (String, FileStamp, Result<Option<Song>, String>);
 
 #[derive(Debug)]
 pub struct MusicLibrary {
     pub library: Mutex<HashMap<String, Song>>,
+    stamps: Mutex<HashMap<String, FileStamp>>,
+    scan_report: Mutex<ScanReport>,
+    /// Keyed by track id.
+    track_stats: Mutex<HashMap<String, TrackStats>>,
+    index: SearchIndex,
+    reindex_running: AtomicBool,
+    reindex_cancelled: AtomicBool,
+    /// Held for the whole scan, so a restarted scan waits for the old one.
+    reindex_slot: Mutex<()>,
+    /// Bumped by every `request_reindex`; only the latest request runs.
+    reindex_requests: AtomicUsize,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReindexOutcome {
+    Finished(ReindexSummary),
+    Cancelled,
+    /// A newer request replaced this one before it started.
+    Superseded,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReindexSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub total: usize,
+    /// Entries in the scan report; see `get_scan_report`.
+    pub issues: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReindexProgress {
+    pub discovered: usize,
+    pub changed: usize,
+    pub processed: usize,
+    pub current_path: Option<String>,
+    pub eta_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum LibraryChange {
+    Changed(PathBuf),
+    Removed(PathBuf),
+    Moved { from: PathBuf, to: PathBuf },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovedTrack {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LibraryUpdate {
+    #[serde(flatten)]
+    pub summary: ReindexSummary,
+    pub moved: Vec<MovedTrack>,
+}
+
+impl LibraryUpdate {
+    pub fn has_changes(&self) -> bool {
+        self.summary.added > 0
+            || self.summary.updated > 0
+            || self.summary.removed > 0
+            || !self.moved.is_empty()
+    }
 }
 
 impl MusicLibrary {
     pub fn new() -> Self {
-        let music_library = index_music_files();
         Self {
-            library: Mutex::new(music_library),
+            library: Mutex::new(HashMap::new()),
+            stamps: Mutex::new(HashMap::new()),
+            scan_report: Mutex::new(ScanReport::default()),
+            track_stats: Mutex::new(HashMap::new()),
+            index: SearchIndex::new(),
+            reindex_running: AtomicBool::new(false),
+            reindex_cancelled: AtomicBool::new(false),
+            reindex_slot: Mutex::new(()),
+            reindex_requests: AtomicUsize::new(0),
         }
     }
 
-    pub fn reindex(&self) -> usize {
-        let new_library = index_music_files();
-        let mut library = self.library.lock().unwrap();
-        *library = new_library;
-        library.len()
+    pub fn is_reindexing(&self) -> bool {
+        self.reindex_running.load(Ordering::SeqCst)
     }
 
-    pub fn get_stats(&self) -> usize {
-        let library = self.library.lock().unwrap();
-        library.len()
+    pub fn cancel_reindex(&self) -> bool {
+        if !self.is_reindexing() {
+            return false;
+        }
+        self.reindex_cancelled.store(true, Ordering::SeqCst);
+        true
     }
 
-    pub fn search(&self, query: &str) -> Vec<Song> {
-        let library = self.library.lock().unwrap();
-        let query_lower = query.trim().to_lowercase();
-
-        let mut results: Vec<Song> = library
-            .values()
-            .filter(|song| {
-                song.title.to_lowercase().contains(&query_lower)
-                    || song.subtitle.to_lowercase().contains(&query_lower)
-                    || song.album.to_lowercase().contains(&query_lower)
-            })
+    /// Registers a scan request and returns its number for `reindex_with`.
+    pub fn request_reindex(&self) -> usize {
+        self.reindex_requests.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Waits for a running scan to end, then rescans the library roots,
+    /// unless a newer request was made in the meantime.
+    pub fn reindex_with<F>(&self, request: usize, on_progress: F) -> ReindexOutcome
+    where
+        F: Fn(&ReindexProgress) + Sync,
+    {
+        let _slot = self
+            .reindex_slot
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if self.reindex_requests.load(Ordering::SeqCst) != request {
+            return ReindexOutcome::Superseded;
+        }
+        self.reindex_running.store(true, Ordering::SeqCst);
+        self.reindex_cancelled.store(false, Ordering::SeqCst);
+
+        let summary = self.run_reindex(&on_progress);
+
+        self.reindex_running.store(false, Ordering::SeqCst);
+        match summary {
+            Some(summary) => ReindexOutcome::Finished(summary),
+            None => ReindexOutcome::Cancelled,
+        }
+    }
+
+    fn run_reindex(
+        &self,
+        on_progress: &(dyn Fn(&ReindexProgress) + Sync),
+    ) -> Option<ReindexSummary> {
+        let started = SystemTime::now();
+        let options = ScanOptions::load();
+        let mut report = ScanReport::default();
+        let files = scan_music_files(&options, &mut report);
+        if self.reindex_cancelled.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        // Parsing happens without holding the locks, so the watcher, tag edits
+        // and stats imports can keep changing entries meanwhile. Only this
+        // scan's differences are applied afterwards.
+        let snapshot = self.stamps.lock().unwrap().clone();
+
+        // Only files whose size, mtime or inode changed since the last scan
+        // are parsed again; everything else keeps its existing entry.
+        let changed: Vec<(PathBuf, FileStamp)> = files
+            .iter()
+            .filter(|(path, stamp)| snapshot.get(path.to_string_lossy().as_ref()) != Some(stamp))
             .cloned()
             .collect();
 
-        results.sort_by(|a, b| {
-            let score = |song: &Song| -> usize {
-                if song.title.to_lowercase().contains(&query_lower) {
-                    return 0;
+        on_progress(&ReindexProgress {
+            discovered: files.len(),
+            changed: changed.len(),
+            processed: 0,
+            current_path: None,
+            eta_seconds: None,
+        });
+
+        let parsed = read_songs_parallel(
+            &changed,
+            &options,
+            files.len(),
+            &self.reindex_cancelled,
+            on_progress,
+        )?;
+
+        let seen: HashSet<String> = files
+            .iter()
+            .map(|(path, _)| path.to_string_lossy().to_string())
+            .collect();
+        let mut summary = ReindexSummary::default();
+        let mut indexed = Vec::new();
+        let mut removed = Vec::new();
+
+        let mut library = self.library.lock().unwrap();
+        let mut stamps = self.stamps.lock().unwrap();
+
+        for (path, stamp, song) in parsed {
+            let song = song.unwrap_or_else(|error| {
+                report.push(ScanIssueKind::UnparseableFile, Path::new(&path), error);
+                None
+            });
+            // Someone else refreshed the file since the snapshot; their entry
+            // is newer than what this scan read.
+            if stamps.get(&path) != snapshot.get(&path) {
+                continue;
+            }
+            match song.map(|song| self.with_stats(song)) {
+                Some(song) => {
+                    if library.insert(path.clone(), song).is_some() {
+                        summary.updated += 1;
+                    } else {
+                        summary.added += 1;
+                    }
+                    indexed.push(path.clone());
+                    stamps.insert(path, stamp);
                 }
-                if song.subtitle.to_lowercase().contains(&query_lower) {
-                    return 1;
+                None => {
+                    if library.remove(&path).is_some() {
+                        removed.push(path.clone());
+                    }
+                    stamps.remove(&path);
                 }
-                if song.album.to_lowercase().contains(&query_lower) {
-                    return 2;
+            }
+        }
+
+        // Files that were gone during the scan, unless they were added or
+        // refreshed since the snapshot.
+        let gone: Vec<String> = snapshot
+            .iter()
+            .filter(|(path, stamp)| !seen.contains(*path) && stamps.get(*path) == Some(*stamp))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in gone {
+            stamps.remove(&path);
+            if library.remove(&path).is_some() {
+                removed.push(path);
+            }
+        }
+
+        self.index.update(
+            indexed.iter().filter_map(|path| library.get(path)),
+            removed.iter().map(String::as_str),
+        );
+        summary.removed = removed.len();
+        summary.total = library.len();
+        summary.issues = report.issues.len();
+        report.finish();
+        *self.scan_report.lock().unwrap() = report;
+
+        // The cache is walked without the locks held. Covers written after
+        // the scan started may belong to tracks that are being refreshed
+        // right now, so they are kept.
+        let used_covers = used_cover_hashes(&library);
+        drop(stamps);
+        drop(library);
+        if let Err(e) = cleanup_unused_covers(&used_covers, started) {
+            eprintln!("Error cleaning up unused covers: {}", e);
+        }
+
+        println!(
+            "Indexed {} songs ({} added, {} updated, {} removed)",
+            summary.total, summary.added, summary.updated, summary.removed
+        );
+        Some(summary)
+    }
+
+    pub fn apply_changes(&self, changes: &[LibraryChange]) -> LibraryUpdate {
+        let options = ScanOptions::load();
+        let mut update = LibraryUpdate::default();
+
+        for change in changes {
+            match change {
+                LibraryChange::Changed(path) => self.refresh_path(path, &options, &mut update),
+                LibraryChange::Removed(path) => self.remove_entries(path, &mut update),
+                LibraryChange::Moved { from, to } => {
+                    if options.includes(to) {
+                        self.move_entries(from, to, &mut update);
+                        self.refresh_path(to, &options, &mut update);
+                    } else {
+                        self.remove_entries(from, &mut update);
+                    }
                 }
-                3
-            };
+            }
+        }
+
+        update.summary.total = self.get_stats();
+        update
+    }
+
+    /// Re-reads the given files, e.g. after their tags were rewritten.
+    pub fn refresh_tracks(&self, paths: &[String]) -> LibraryUpdate {
+        let options = ScanOptions::load();
+        let mut update = LibraryUpdate::default();
+        for path in paths {
+            self.refresh_path(Path::new(path), &options, &mut update);
+        }
+        update.summary.total = self.get_stats();
+        update
+    }
+
+    /// Drops the given files from the library without touching the disk.
+    pub fn remove_tracks(&self, paths: &[String]) -> LibraryUpdate {
+        let mut update = LibraryUpdate::default();
+        for path in paths {
+            self.remove_entries(Path::new(path), &mut update);
+        }
+        update.summary.total = self.get_stats();
+        update
+    }
+
+    fn refresh_path(&self, path: &Path, options: &ScanOptions, update: &mut LibraryUpdate) {
+        if !options.includes(path) {
+            return;
+        }
+
+        if path.is_dir() {
+            // Problems are logged here and reported by the next full scan.
+            let mut report = ScanReport::default();
+            let files = scan_directory(path, options, &mut report);
+            for issue in &report.issues {
+                eprintln!("Cannot scan {}: {}", issue.path, issue.error);
+            }
+            for (file, stamp) in files {
+                self.refresh_file(&file, stamp, options, update);
+            }
+            return;
+        }
+
+        if !is_audio_file(path) {
+            return;
+        }
+
+        match file_stamp(path, &options.metadata.covers.folder_names) {
+            Some(stamp) => self.refresh_file(path, stamp, options, update),
+            None => self.remove_entries(path, update),
+        }
+    }
+
+    fn refresh_file(
+        &self,
+        path: &Path,
+        stamp: FileStamp,
+        options: &ScanOptions,
+        update: &mut LibraryUpdate,
+    ) {
+        let key = path.to_string_lossy().to_string();
+        if self.stamps.lock().unwrap().get(&key) == Some(&stamp) {
+            return;
+        }
+
+        let song = read_song(path, options)
+            .unwrap_or_default()
+            .map(|song| self.with_stats(song));
+        let mut library = self.library.lock().unwrap();
+        let mut stamps = self.stamps.lock().unwrap();
+
+        match song {
+            Some(song) => {
+                self.index.update([&song], []);
+                if library.insert(key.clone(), song).is_some() {
+                    update.summary.updated += 1;
+                } else {
+                    update.summary.added += 1;
+                }
+                stamps.insert(key, stamp);
+            }
+            None => {
+                if library.remove(&key).is_some() {
+                    update.summary.removed += 1;
+                    self.index.update([], [key.as_str()]);
+                }
+                stamps.remove(&key);
+            }
+        }
+    }
+
+    fn remove_entries(&self, path: &Path, update: &mut LibraryUpdate) {
+        let mut library = self.library.lock().unwrap();
+        let mut stamps = self.stamps.lock().unwrap();
 
-            score(a)
-                .cmp(&score(b))
-                .then_with(|| a.subtitle.cmp(&b.subtitle))
-                .then_with(|| a.album.cmp(&b.album))
-                .then_with(|| a.title.cmp(&b.title))
+        let mut removed = Vec::new();
+        library.retain(|key, _| {
+            let keep = !Path::new(key).starts_with(path);
+            if !keep {
+                removed.push(key.clone());
+            }
+            keep
         });
+        stamps.retain(|key, _| !Path::new(key).starts_with(path));
+        self.index.update([], removed.iter().map(String::as_str));
+        update.summary.removed += removed.len();
+    }
+
+    fn move_entries(&self, from: &Path, to: &Path, update: &mut LibraryUpdate) {
+        let mut library = self.library.lock().unwrap();
+        let mut stamps = self.stamps.lock().unwrap();
+
+        let affected: Vec<String> = library
+            .keys()
+            .filter(|key| Path::new(key).starts_with(from))
+            .cloned()
+            .collect();
+
+        for old_key in affected {
+            let Ok(relative) = Path::new(&old_key).strip_prefix(from) else {
+                continue;
+            };
+            let new_path = if relative.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(relative)
+            };
+            let new_key = new_path.to_string_lossy().to_string();
+
+            if let Some(mut song) = library.remove(&old_key) {
+                song.path = new_key.clone();
+                self.index.update([&song], [old_key.as_str()]);
+                library.insert(new_key.clone(), song);
+            }
+            if let Some(stamp) = stamps.remove(&old_key) {
+                stamps.insert(new_key.clone(), stamp);
+            }
+
+            update.moved.push(MovedTrack {
+                from: old_key,
+                to: new_key,
+            });
+        }
+    }
+
+    /// Moves playlist and history references from files that disappeared to
+    /// the indexed track with the same id, e.g. after folders were reorganised
+    /// while Rift was not running.
+    pub fn relink_references(
+        &self,
+        playlists: &PlaylistStore,
+        history: &ListeningHistoryStore,
+    ) -> Vec<MovedTrack> {
+        let (ids_by_path, paths_by_id) = {
+            let library = self.library.lock().unwrap();
+            let ids_by_path: HashMap<String, String> = library
+                .iter()
+                .map(|(path, song)| (path.clone(), song.track_id.clone()))
+                .collect();
+            let paths_by_id: HashMap<String, String> = library
+                .iter()
+                .map(|(path, song)| (song.track_id.clone(), path.clone()))
+                .collect();
+            (ids_by_path, paths_by_id)
+        };
+
+        let mut moved = Vec::new();
+        match playlists.relink_tracks(&ids_by_path, &paths_by_id) {
+            Ok(tracks) => moved.extend(tracks),
+            Err(error) => eprintln!("Failed to relink playlist tracks: {error}"),
+        }
+        match history.relink_tracks(&ids_by_path, &paths_by_id) {
+            Ok(tracks) => moved.extend(tracks),
+            Err(error) => eprintln!("Failed to relink listening history: {error}"),
+        }
+
+        if !moved.is_empty() {
+            println!("Relinked {} moved tracks", moved.len());
+        }
+        moved
+    }
+
+    pub fn get_stats(&self) -> usize {
+        let library = self.library.lock().unwrap();
+        library.len()
+    }
+
+    pub fn search(
+        &self,
+        query: &str,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<Song>, QueryError> {
+        let query = Query::parse(query)?;
+        let library = self.library.lock().unwrap();
+        Ok(search_songs(&library, &self.index, &query, offset, limit))
+    }
+
+    pub fn list_tracks(
+        &self,
+        options: &TrackListOptions,
+        play_counts: &HashMap<String, usize>,
+    ) -> Result<TrackPage, QueryError> {
+        let query = match options.query.as_deref() {
+            Some(query) if !query.trim().is_empty() => Some(Query::parse(query)?),
+            _ => None,
+        };
+
+        let library = self.library.lock().unwrap();
+        let songs = match &query {
+            Some(query) => matching_songs(&library, &self.index, query, None),
+            None => library.values().collect(),
+        };
+        Ok(list_tracks(songs, options, play_counts))
+    }
+
+    pub fn albums(&self) -> Vec<AlbumSummary> {
+        let library = self.library.lock().unwrap();
+        list_albums(&library)
+    }
+
+    pub fn album(&self, key: &str) -> Option<AlbumDetail> {
+        let library = self.library.lock().unwrap();
+        find_album(&library, key)
+    }
+
+    pub fn artists(&self) -> Vec<ArtistSummary> {
+        let library = self.library.lock().unwrap();
+        list_artists(&library)
+    }
+
+    pub fn artist(&self, key: &str) -> Option<ArtistDetail> {
+        let library = self.library.lock().unwrap();
+        find_artist(&library, &artist_key(key))
+    }
+
+    /// Replaces the stored per-track stats and applies them to the songs
+    /// already indexed.
+    pub fn set_track_stats(&self, stats: HashMap<String, TrackStats>) {
+        *self.track_stats.lock().unwrap() = stats;
+        let mut library = self.library.lock().unwrap();
+        for song in library.values_mut() {
+            *song = self.with_stats(song.clone());
+        }
+    }
+
+    fn with_stats(&self, mut song: Song) -> Song {
+        if let Some(stats) = self.track_stats.lock().unwrap().get(&song.track_id) {
+            song.rating = stats.rating;
+            if let Some(added_at) = stats.added_at {
+                song.added_at = added_at;
+            }
+        }
+        song
+    }
 
-        results
+    pub fn scan_report(&self) -> ScanReport {
+        self.scan_report.lock().unwrap().clone()
     }
 
     pub fn by_path(&self, path: &str) -> Option<Song> {
@@ -71,3 +563,93 @@ impl MusicLibrary {
         library.get(path).cloned()
     }
 }
+
+fn read_songs_parallel(
+    files: &[(PathBuf, FileStamp)],
+    options: &ScanOptions,
+    discovered: usize,
+    cancelled: &AtomicBool,
+    on_progress: &(dyn Fn(&ReindexProgress) + Sync),
+) -> Option<Vec<ParsedFile>> {
+    let workers = thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(4)
+        .clamp(1, MAX_REINDEX_WORKERS);
+    let next = AtomicUsize::new(0);
+    let processed = AtomicUsize::new(0);
+    let started = Instant::now();
+    let last_report = Mutex::new(started);
+
+    let parsed = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut parsed = Vec::new();
+                    while !cancelled.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((path, stamp)) = files.get(index) else {
+                            break;
+                        };
+
+                        let key = path.to_string_lossy().to_string();
+                        // A decoder panic on one broken file must not take
+                        // the rest of this worker's batch with it.
+                        let song =
+                            panic::catch_unwind(AssertUnwindSafe(|| read_song(path, options)))
+                                .unwrap_or_else(|_| Err("Reading the file panicked".to_string()));
+                        parsed.push((key.clone(), stamp.clone(), song));
+                        let done = processed.fetch_add(1, Ordering::Relaxed) + 1;
+
+                        let Ok(mut last) = last_report.try_lock() else {
+                            continue;
+                        };
+                        if last.elapsed() < PROGRESS_INTERVAL && done < files.len() {
+                            continue;
+                        }
+                        *last = Instant::now();
+
+                        let per_file = started.elapsed().as_secs_f64() / done as f64;
+                        on_progress(&ReindexProgress {
+                            discovered,
+                            changed: files.len(),
+                            processed: done,
+                            current_path: Some(key),
+                            eta_seconds: Some((per_file * (files.len() - done) as f64) as u64),
+                        });
+                    }
+                    parsed
+                })
+            })
+            .collect();
+
+        let mut parsed = Vec::new();
+        for handle in handles {
+            match handle.join() {
+                Ok(batch) => parsed.extend(batch),
+                // Files of the lost batch keep their old entries and stamps,
+                // so the next scan parses them again.
+                Err(_) => eprintln!("A reindex worker panicked; its files were not updated"),
+            }
+        }
+        parsed
+    });
+
+    if cancelled.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(parsed)
+}
+
+/// `Ok(None)` for files that parse but are shorter than the configured
+/// minimum.
+fn read_song(path: &Path, options: &ScanOptions) -> Result<Option<Song>, String> {
+    match read_audio_metadata(&path.to_path_buf(), &options.metadata) {
+        Ok(song) => {
+            Ok(Some(song).filter(|song| song.duration_ms / 1000 >= options.min_duration_seconds))
+        }
+        Err(error) => {
+            eprintln!("Error reading metadata for {:?}: {}", path, error);
+            Err(error)
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::browse::{album_artist_of, album_key, artist_key, compare_album_order, is_credited};
+use super::search::fold_text;
+use crate::models::models::Song;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackSortKey {
+    Title,
+    #[default]
+    Artist,
+    Album,
+    AddedAt,
+    Duration,
+    PlayCount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TrackListOptions {
+    pub offset: usize,
+    pub limit: Option<usize>,
+    pub sort: TrackSortKey,
+    pub direction: SortDirection,
+    /// Same syntax as the command palette search.
+    pub query: Option<String>,
+    pub artist_key: Option<String>,
+    pub album_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackPage {
+    pub total: usize,
+    pub offset: usize,
+    pub items: Vec<Song>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Text(String),
+    Number(i64),
+}
+
+fn sort_value(song: &Song, sort: TrackSortKey, play_counts: &HashMap<String, usize>) -> SortValue {
+    match sort {
+        TrackSortKey::Title => SortValue::Text(fold_text(&song.title)),
+        TrackSortKey::Artist => SortValue::Text(fold_text(album_artist_of(song))),
+        TrackSortKey::Album => SortValue::Text(fold_text(&song.album)),
+        TrackSortKey::AddedAt => SortValue::Number(song.added_at),
+        TrackSortKey::Duration => SortValue::Number(song.duration_ms as i64),
+        TrackSortKey::PlayCount => {
+            SortValue::Number(play_counts.get(&song.path).copied().unwrap_or(0) as i64)
+        }
+    }
+}
+
+/// Ties fall back to album order, so artist and album sorts keep each
+/// album's tracks in sequence and pages never overlap.
+fn compare_ties(a: &Song, b: &Song) -> Ordering {
+    album_artist_of(a)
+        .cmp(album_artist_of(b))
+        .then_with(|| a.album.cmp(&b.album))
+        .then_with(|| compare_album_order(a, b))
+}
+
+/// Sorts and pages `songs`, which the caller has already narrowed down to
+/// the ones matching `options.query`.
+pub fn list_tracks(
+    songs: Vec<&Song>,
+    options: &TrackListOptions,
+    play_counts: &HashMap<String, usize>,
+) -> TrackPage {
+    let artist = options.artist_key.as_deref().map(artist_key);
+
+    let mut tracks: Vec<(SortValue, &Song)> = songs
+        .into_iter()
+        .filter(|song| {
+            artist.as_ref().is_none_or(|key| {
+                is_credited(song, key) || artist_key(album_artist_of(song)) == *key
+            })
+        })
+        .filter(|song| {
+            options
+                .album_key
+                .as_ref()
+                .is_none_or(|key| album_key(song) == *key)
+        })
+        .map(|song| (sort_value(song, options.sort, play_counts), song))
+        .collect();
+    let total = tracks.len();
+
+    let compare = |a: &(SortValue, &Song), b: &(SortValue, &Song)| {
+        let primary = a.0.cmp(&b.0);
+        let primary = if options.direction == SortDirection::Desc {
+            primary.reverse()
+        } else {
+            primary
+        };
+        primary.then_with(|| compare_ties(a.1, b.1))
+    };
+
+    // Only the requested page has to be in order.
+    let end = match options.limit {
+        Some(limit) => options.offset.saturating_add(limit).min(total),
+        None => total,
+    };
+    if options.offset >= end {
+        return TrackPage {
+            total,
+            offset: options.offset,
+            items: Vec::new(),
+        };
+    }
+    if end < total {
+        tracks.select_nth_unstable_by(end - 1, compare);
+        tracks.truncate(end);
+    }
+    tracks.sort_by(compare);
+
+    TrackPage {
+        total,
+        offset: options.offset,
+        items: tracks
+            .into_iter()
+            .skip(options.offset)
+            .map(|(_, song)| song.clone())
+            .collect(),
+    }
+}
@@ -0,0 +1,368 @@
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, FileType, TaggedFile, TaggedFileExt};
+use lofty::id3::v2::{
+    Frame, Id3v2Tag, SyncTextContentType, SynchronizedTextFrame, TimestampFormat,
+};
+use lofty::mpeg::MpegFile;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const LYRICS_LINE_EVENT: &str = "lyrics-line";
+
+// Samples per MPEG-1 Layer III frame, for SYLT frames timed in MPEG frames.
+const SAMPLES_PER_MPEG_FRAME: u64 = 1152;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LyricLine {
+    /// Missing for plain, unsynchronised lyrics.
+    pub time_ms: Option<u64>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LyricsSource {
+    Sidecar,
+    Embedded,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Lyrics {
+    pub synced: bool,
+    pub source: LyricsSource,
+    pub lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    /// Index of the line being sung at `position_ms`, if the lyrics are
+    /// synced and the first line has started.
+    pub fn active_line(&self, position_ms: u64) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        self.lines
+            .partition_point(|line| line.time_ms.unwrap_or(0) <= position_ms)
+            .checked_sub(1)
+    }
+}
+
+pub fn is_lyrics_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("lrc"))
+}
+
+/// The `.lrc` file next to `audio_path` with the same file stem.
+pub fn sidecar_path(audio_path: &Path) -> Option<PathBuf> {
+    ["lrc", "LRC"]
+        .iter()
+        .map(|extension| audio_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Reads `path` like `lofty::read_from_path`, but keeps MP3 SYLT frames,
+/// which lofty drops when converting to its generic tag.
+pub fn read_tagged_file(path: &Path) -> lofty::error::Result<(TaggedFile, Option<Lyrics>)> {
+    let probe = Probe::open(path)?;
+    if probe.file_type() != Some(FileType::Mpeg) {
+        return Ok((probe.read()?, None));
+    }
+
+    let file = MpegFile::read_from(&mut probe.into_inner(), ParseOptions::new())?;
+    let sample_rate = file.properties().sample_rate();
+    let synced = file
+        .id3v2()
+        .and_then(|tag| synchronized_lyrics(tag, sample_rate));
+    Ok((file.into(), synced))
+}
+
+/// Lyrics stored in the file's tags: SYLT when present, otherwise the
+/// USLT, LYRICS or ©lyr text, which may itself be in LRC format.
+pub fn embedded_lyrics(tagged_file: &TaggedFile, synced: Option<Lyrics>) -> Option<Lyrics> {
+    if synced.is_some() {
+        return synced;
+    }
+    let text = tagged_file
+        .tags()
+        .iter()
+        .find_map(|tag| tag.get_string(&ItemKey::Lyrics))?;
+    parse_lyrics(text, LyricsSource::Embedded)
+}
+
+/// Sidecar lyrics win over embedded ones since they are easier to fix.
+pub fn read_lyrics(path: &Path) -> Result<Option<Lyrics>, String> {
+    if let Some(sidecar) = sidecar_path(path) {
+        let bytes = fs::read(&sidecar).map_err(|error| error.to_string())?;
+        let text = String::from_utf8_lossy(&bytes);
+        if let Some(lyrics) = parse_lyrics(&text, LyricsSource::Sidecar) {
+            return Ok(Some(lyrics));
+        }
+    }
+
+    let (tagged_file, synced) = read_tagged_file(path).map_err(|error| error.to_string())?;
+    Ok(embedded_lyrics(&tagged_file, synced))
+}
+
+fn synchronized_lyrics(tag: &Id3v2Tag, sample_rate: u32) -> Option<Lyrics> {
+    tag.into_iter().find_map(|frame| {
+        let Frame::Binary(binary) = frame else {
+            return None;
+        };
+        if binary.id().as_str() != "SYLT" {
+            return None;
+        }
+        let sylt = SynchronizedTextFrame::parse(&binary.data, binary.flags()).ok()?;
+        if !matches!(
+            sylt.content_type,
+            SyncTextContentType::Lyrics | SyncTextContentType::TextTranscription
+        ) {
+            return None;
+        }
+
+        let to_ms = |timestamp: u32| match sylt.timestamp_format {
+            TimestampFormat::MS => Some(timestamp as u64),
+            TimestampFormat::MPEG if sample_rate > 0 => {
+                Some(timestamp as u64 * SAMPLES_PER_MPEG_FRAME * 1000 / sample_rate as u64)
+            }
+            TimestampFormat::MPEG => None,
+        };
+
+        // Word-timed frames mark line starts with a newline; otherwise each
+        // entry is a line of its own.
+        let word_timed = sylt
+            .content
+            .iter()
+            .any(|(_, text)| text.starts_with(['\n', '\r']));
+        let mut lines: Vec<LyricLine> = Vec::new();
+        for (timestamp, text) in &sylt.content {
+            let time_ms = to_ms(*timestamp)?;
+            let starts_line = !word_timed || text.starts_with(['\n', '\r']) || lines.is_empty();
+            let text = text.trim_start_matches(['\n', '\r']);
+            match lines.last_mut() {
+                Some(line) if !starts_line => line.text.push_str(text),
+                _ => lines.push(LyricLine {
+                    time_ms: Some(time_ms),
+                    text: text.to_string(),
+                }),
+            }
+        }
+        for line in &mut lines {
+            line.text = line.text.trim().to_string();
+        }
+        lines.sort_by_key(|line| line.time_ms);
+
+        (!lines.is_empty()).then_some(Lyrics {
+            synced: true,
+            source: LyricsSource::Embedded,
+            lines,
+        })
+    })
+}
+
+/// Parses LRC text into synced lines, or falls back to plain lines when no
+/// line carries a timestamp.
+pub fn parse_lyrics(text: &str, source: LyricsSource) -> Option<Lyrics> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut offset_ms = 0i64;
+    let mut synced = Vec::new();
+    let mut plain = Vec::new();
+
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        let mut is_tag = false;
+
+        while let Some(inner) = rest.strip_prefix('[') {
+            let Some(end) = inner.find(']') else {
+                break;
+            };
+            let label = &inner[..end];
+            if let Some(time_ms) = parse_timestamp(label) {
+                times.push(time_ms);
+            } else if let Some((key, value)) = label.split_once(':') {
+                if key.trim().eq_ignore_ascii_case("offset") {
+                    offset_ms = value.trim().parse().unwrap_or(0);
+                }
+                is_tag = true;
+            } else {
+                break;
+            }
+            rest = inner[end + 1..].trim_start();
+        }
+
+        if times.is_empty() {
+            // ID tags such as [ar:...] carry no lyric text.
+            if !is_tag {
+                plain.push(raw.trim().to_string());
+            }
+            continue;
+        }
+        let line = strip_word_timestamps(rest);
+        for time_ms in times {
+            synced.push((time_ms, line.clone()));
+        }
+    }
+
+    if !synced.is_empty() {
+        synced.sort_by_key(|(time_ms, _)| *time_ms);
+        // A positive offset shows lyrics earlier.
+        let lines = synced
+            .into_iter()
+            .map(|(time_ms, text)| LyricLine {
+                time_ms: Some((time_ms as i64 - offset_ms).max(0) as u64),
+                text,
+            })
+            .collect();
+        return Some(Lyrics {
+            synced: true,
+            source,
+            lines,
+        });
+    }
+
+    while plain.last().is_some_and(|line| line.is_empty()) {
+        plain.pop();
+    }
+    let first = plain.iter().position(|line| !line.is_empty())?;
+    Some(Lyrics {
+        synced: false,
+        source,
+        lines: plain
+            .drain(first..)
+            .map(|text| LyricLine {
+                time_ms: None,
+                text,
+            })
+            .collect(),
+    })
+}
+
+/// `mm:ss`, `mm:ss.xx`, `mm:ss.xxx` or `mm:ss:xx` in milliseconds.
+fn parse_timestamp(label: &str) -> Option<u64> {
+    let (minutes, rest) = label.trim().split_once(':')?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (rest, ""),
+    };
+    if minutes.is_empty()
+        || seconds.is_empty()
+        || !minutes.bytes().all(|byte| byte.is_ascii_digit())
+        || !seconds.bytes().all(|byte| byte.is_ascii_digit())
+        || !fraction.bytes().all(|byte| byte.is_ascii_digit())
+        || fraction.len() > 3
+    {
+        return None;
+    }
+
+    let minutes: u64 = minutes.parse().ok()?;
+    let seconds: u64 = seconds.parse().ok()?;
+    let fraction_ms = match fraction.len() {
+        0 => 0,
+        length => fraction.parse::<u64>().ok()? * 10u64.pow(3 - length as u32),
+    };
+    Some((minutes * 60 + seconds) * 1000 + fraction_ms)
+}
+
+/// Enhanced LRC times single words with `<mm:ss.xx>`; only the line timing
+/// is kept.
+fn strip_word_timestamps(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        if parse_timestamp(&rest[start + 1..start + end]).is_none() {
+            text.push_str(&rest[..start + end + 1]);
+        } else {
+            text.push_str(&rest[..start]);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lyrics: &Lyrics) -> Vec<(Option<u64>, &str)> {
+        lyrics
+            .lines
+            .iter()
+            .map(|line| (line.time_ms, line.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_synced_lines_in_time_order() {
+        let text = "\u{feff}[ar:Artist]\n[ti:Title]\n[00:12.50]Second\n[00:01.00]First\n";
+        let lyrics = parse_lyrics(text, LyricsSource::Sidecar).unwrap();
+        assert!(lyrics.synced);
+        assert_eq!(
+            lines(&lyrics),
+            [(Some(1000), "First"), (Some(12500), "Second")]
+        );
+    }
+
+    #[test]
+    fn repeated_timestamps_and_offset() {
+        let text = "[offset:500]\n[00:01.00][00:03.00]Chorus\n[00:00.20]Intro";
+        let lyrics = parse_lyrics(text, LyricsSource::Sidecar).unwrap();
+        assert_eq!(
+            lines(&lyrics),
+            [
+                (Some(0), "Intro"),
+                (Some(500), "Chorus"),
+                (Some(2500), "Chorus")
+            ]
+        );
+    }
+
+    #[test]
+    fn strips_word_timestamps() {
+        let text = "[00:01.00]<00:01.00>Hello <00:01.50>world <b>";
+        let lyrics = parse_lyrics(text, LyricsSource::Embedded).unwrap();
+        assert_eq!(lines(&lyrics), [(Some(1000), "Hello world <b>")]);
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        assert_eq!(parse_timestamp("01:02"), Some(62_000));
+        assert_eq!(parse_timestamp("01:02.5"), Some(62_500));
+        assert_eq!(parse_timestamp("01:02.05"), Some(62_050));
+        assert_eq!(parse_timestamp("01:02:345"), Some(62_345));
+        assert_eq!(parse_timestamp("01:02.3456"), None);
+        assert_eq!(parse_timestamp("ar:Artist"), None);
+        assert_eq!(parse_timestamp(":02"), None);
+    }
+
+    #[test]
+    fn plain_text_keeps_inner_blank_lines() {
+        let text = "\n\nVerse one\n\nVerse two\n\n";
+        let lyrics = parse_lyrics(text, LyricsSource::Sidecar).unwrap();
+        assert!(!lyrics.synced);
+        assert_eq!(
+            lines(&lyrics),
+            [(None, "Verse one"), (None, ""), (None, "Verse two")]
+        );
+        assert_eq!(lyrics.active_line(10_000), None);
+    }
+
+    #[test]
+    fn empty_text_has_no_lyrics() {
+        assert!(parse_lyrics("[ar:Artist]\n\n", LyricsSource::Sidecar).is_none());
+    }
+
+    #[test]
+    fn active_line_follows_position() {
+        let text = "[00:01.00]One\n[00:02.00]Two";
+        let lyrics = parse_lyrics(text, LyricsSource::Sidecar).unwrap();
+        assert_eq!(lyrics.active_line(500), None);
+        assert_eq!(lyrics.active_line(1000), Some(0));
+        assert_eq!(lyrics.active_line(5000), Some(1));
+    }
+}
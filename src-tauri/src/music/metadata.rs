@@ -1,40 +1,89 @@
-use crate::models::models::Song;
-use lofty::{file::AudioFile, file::TaggedFileExt, read_from_path, tag::Accessor};
+use super::artists::{split_artists, ArtistOptions};
+use super::artwork::{prepare_cover, write_atomically};
+use super::lyrics::{embedded_lyrics, read_tagged_file, sidecar_path};
+use crate::models::models::{CoverPriority, Song};
+use lofty::file::FileType;
+use lofty::picture::Picture;
+use lofty::properties::FileProperties;
+use lofty::tag::ItemKey;
+use lofty::{file::AudioFile, file::TaggedFileExt, tag::Accessor};
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::time::UNIX_EPOCH;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-fn extract_and_cache_cover_with_hash(
-    audio_path: &Path,
-    cache_dir: &Path,
-) -> io::Result<(Option<String>, Option<String>)> {
-    let tagged_file = match read_from_path(audio_path) {
-        Ok(file) => file,
-        Err(_) => return Ok((None, None)),
-    };
+/// Path, size and modification time of the file a cover comes from: the
+/// audio file for embedded artwork, the image itself for folder artwork.
+/// Lets a rescan reuse the cached cover without hashing the image again,
+/// and folder artwork is hashed once per album rather than once per track.
+type CoverKey = (PathBuf, u64, Option<SystemTime>);
+/// Image files in a folder as of its modification time.
+type FolderListing = (Option<SystemTime>, Vec<PathBuf>);
 
-    let tag = match tagged_file.primary_tag() {
-        Some(primary) => primary,
-        None => match tagged_file.first_tag() {
-            Some(first) => first,
-            None => return Ok((None, None)),
-        },
-    };
+const FOLDER_IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "gif", "bmp"];
 
-    let picture = match tag.pictures().first() {
-        Some(pic) => pic,
-        None => return Ok((None, None)),
-    };
+static COVER_INDEX: OnceLock<Mutex<HashMap<CoverKey, String>>> = OnceLock::new();
+static FOLDER_COVER_INDEX: OnceLock<Mutex<HashMap<CoverKey, String>>> = OnceLock::new();
+static FOLDER_IMAGES: OnceLock<Mutex<HashMap<PathBuf, FolderListing>>> = OnceLock::new();
 
-    let mut hasher = Sha256::new();
-    hasher.update(picture.data());
-    let hash = hasher.finalize();
-    let hash_hex = hash
-        .iter()
-        .map(|byte| format!("{:02x}", byte))
-        .collect::<String>();
+#[derive(Debug, Clone, Default)]
+pub struct MetadataOptions {
+    pub covers: CoverOptions,
+    pub artists: ArtistOptions,
+}
+
+/// Where artwork comes from when a file has none embedded, or when folder
+/// images should win.
+#[derive(Debug, Clone, Default)]
+pub struct CoverOptions {
+    /// Folder image names in priority order, e.g. `cover` for any image
+    /// extension or `front.png` for that exact file. Case-insensitive.
+    pub folder_names: Vec<String>,
+    pub priority: CoverPriority,
+}
+
+pub fn cover_cache_dir() -> PathBuf {
+    match dirs::cache_dir() {
+        Some(mut cache) => {
+            cache.push("me.wdkq.rift");
+            cache.push("covers");
+            cache
+        }
+        None => {
+            let mut fallback = PathBuf::from(".");
+            fallback.push(".cover_cache");
+            fallback
+        }
+    }
+}
+
+fn cover_key(path: &Path, metadata: &fs::Metadata) -> CoverKey {
+    (path.to_path_buf(), metadata.len(), metadata.modified().ok())
+}
+
+/// Caches the first embedded picture of `audio_path`. Without the file's
+/// metadata the picture is hashed every time.
+fn cache_cover(
+    audio_path: &Path,
+    audio_metadata: Option<&fs::Metadata>,
+    picture: &Picture,
+    cache_dir: &Path,
+) -> io::Result<String> {
+    let data = picture.data();
+    let key = audio_metadata.map(|metadata| cover_key(audio_path, metadata));
+    let index = COVER_INDEX.get_or_init(|| Mutex::new(HashMap::new()));
+
+    if let Some(file_name) = key
+        .as_ref()
+        .and_then(|key| index.lock().unwrap().get(key).cloned())
+    {
+        if cache_dir.join(&file_name).exists() {
+            return Ok(file_name);
+        }
+    }
 
     let extension = match picture.mime_type() {
         Some(mime_type) => match mime_type.as_str() {
@@ -44,80 +93,253 @@ fn extract_and_cache_cover_with_hash(
             "image/bmp" => "bmp",
             "image/tiff" => "tiff",
             "image/webp" => "webp",
-            _ => {
-                let data = picture.data();
-                if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
-                    "jpg"
-                } else if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
-                    "png"
-                } else if data.starts_with(&[0x47, 0x49, 0x46]) {
-                    "gif"
-                } else if data.starts_with(&[0x42, 0x4D]) {
-                    "bmp"
-                } else {
-                    "jpg"
-                }
-            }
+            _ => sniff_image_extension(data),
         },
-        None => {
-            let data = picture.data();
-            if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
-                "jpg"
-            } else if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
-                "png"
-            } else if data.starts_with(&[0x47, 0x49, 0x46]) {
-                "gif"
-            } else if data.starts_with(&[0x42, 0x4D]) {
-                "bmp"
-            } else {
-                "jpg"
-            }
-        }
+        None => sniff_image_extension(data),
     };
 
+    let file_name = store_cover(data, extension, cache_dir)?;
+    if let Some(key) = key {
+        index.lock().unwrap().insert(key, file_name.clone());
+    }
+    Ok(file_name)
+}
+
+/// Copies a folder image into the cover cache under the same hash naming as
+/// embedded artwork, so `cleanup_unused_covers` treats both alike.
+fn cache_folder_cover(image: &Path, cache_dir: &Path) -> io::Result<String> {
+    let key = cover_key(image, &fs::metadata(image)?);
+    let index = FOLDER_COVER_INDEX.get_or_init(|| Mutex::new(HashMap::new()));
+
+    if let Some(file_name) = index.lock().unwrap().get(&key) {
+        if cache_dir.join(file_name).exists() {
+            return Ok(file_name.clone());
+        }
+    }
+
+    let data = fs::read(image)?;
+    let file_name = store_cover(&data, sniff_image_extension(&data), cache_dir)?;
+    index.lock().unwrap().insert(key, file_name.clone());
+    Ok(file_name)
+}
+
+fn store_cover(data: &[u8], extension: &str, cache_dir: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    let hash = hasher.finalize();
+    let hash_hex = hash
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
     let file_name = format!("{}.{}", hash_hex, extension);
     let cache_file_path = cache_dir.join(&file_name);
 
-    if cache_file_path.exists() {
-        return Ok((Some(file_name), Some(hash_hex)));
+    if !cache_file_path.exists() {
+        fs::create_dir_all(cache_dir)?;
+        write_atomically(&cache_file_path, |file| file.write_all(data))?;
     }
+    Ok(file_name)
+}
 
-    fs::create_dir_all(cache_dir)?;
-    fs::write(&cache_file_path, picture.data())?;
-
-    Ok((Some(file_name), Some(hash_hex)))
+pub fn is_folder_image(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        let extension = extension.to_string_lossy().to_lowercase();
+        FOLDER_IMAGE_EXTENSIONS.contains(&extension.as_str())
+    })
 }
 
-pub fn read_audio_metadata(path: &PathBuf) -> Option<Song> {
-    let cache_dir = match dirs::cache_dir() {
-        Some(mut cache) => {
-            cache.push("me.wdkq.rift");
-            cache.push("covers");
-            cache
+fn folder_images(dir: &Path) -> Vec<PathBuf> {
+    let modified = fs::metadata(dir).and_then(|meta| meta.modified()).ok();
+    let cache = FOLDER_IMAGES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some((listed_at, images)) = cache.lock().unwrap().get(dir) {
+        if modified.is_some() && *listed_at == modified {
+            return images.clone();
         }
-        None => {
-            let mut fallback = PathBuf::from(".");
-            fallback.push(".cover_cache");
-            fallback
-        }
-    };
+    }
+
+    let mut images: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| is_folder_image(path) && path.is_file())
+                .collect()
+        })
+        .unwrap_or_default();
+    images.sort();
 
-    let (cover_file, _cover_hash) = match extract_and_cache_cover_with_hash(path, &cache_dir) {
-        Ok((Some(file), hash)) => (file, hash),
-        Ok((None, _)) => (String::new(), None),
+    cache
+        .lock()
+        .unwrap()
+        .insert(dir.to_path_buf(), (modified, images.clone()));
+    images
+}
+
+/// The first folder image next to `audio_path` matching `names`, in the
+/// order the names are listed.
+pub fn find_folder_cover(audio_path: &Path, names: &[String]) -> Option<PathBuf> {
+    if names.is_empty() {
+        return None;
+    }
+    let images = folder_images(audio_path.parent()?);
+
+    names
+        .iter()
+        .find_map(|name| {
+            let name = name.trim().trim_end_matches(".*").to_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            images.iter().find(|image| {
+                let file_name = image
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().to_lowercase());
+                let stem = image
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_lowercase());
+                file_name.as_deref() == Some(name.as_str())
+                    || stem.as_deref() == Some(name.as_str())
+            })
+        })
+        .cloned()
+}
+
+fn cover_for(
+    path: &Path,
+    file_metadata: Option<&fs::Metadata>,
+    pictures: &[Picture],
+    options: &CoverOptions,
+    cache_dir: &Path,
+) -> String {
+    let embedded = || match cache_cover(path, file_metadata, pictures.first()?, cache_dir) {
+        Ok(file_name) => Some(file_name),
         Err(e) => {
             eprintln!("Warning: Could not cache cover for {:?}: {}", path, e);
-            (String::new(), None)
+            None
+        }
+    };
+    let folder = || {
+        let image = find_folder_cover(path, &options.folder_names)?;
+        match cache_folder_cover(&image, cache_dir) {
+            Ok(file_name) => Some(file_name),
+            Err(e) => {
+                eprintln!("Warning: Could not cache cover {:?}: {}", image, e);
+                None
+            }
         }
     };
 
-    match read_from_path(path) {
-        Ok(tagged_file) => {
-            let tag = match tagged_file.primary_tag() {
-                Some(primary_tag) => primary_tag,
-                None => tagged_file.first_tag()?,
+    match options.priority {
+        CoverPriority::Embedded => embedded().or_else(folder),
+        CoverPriority::Folder => folder().or_else(embedded),
+    }
+    .unwrap_or_default()
+}
+
+/// Identity of a track that survives moves and renames within a drive as
+/// well as tag edits: the audio stream's properties plus the file's
+/// identity. Tags are left out on purpose, and both parts come from the
+/// parse and metadata read `read_audio_metadata` does anyway.
+fn compute_track_id(
+    properties: &FileProperties,
+    codec: &str,
+    file_metadata: Option<&fs::Metadata>,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(codec.as_bytes());
+    hasher.update(properties.duration().as_millis().to_le_bytes());
+    hasher.update(properties.sample_rate().unwrap_or(0).to_le_bytes());
+    hasher.update([
+        properties.channels().unwrap_or(0),
+        properties.bit_depth().unwrap_or(0),
+    ]);
+    hasher.update(file_metadata.map_or(0, file_identity).to_le_bytes());
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The creation time where the file system records one, otherwise the
+/// device and inode. Editing a file in place keeps both.
+fn file_identity(metadata: &fs::Metadata) -> u128 {
+    if let Ok(created) = metadata.created() {
+        if let Ok(since_epoch) = created.duration_since(UNIX_EPOCH) {
+            return since_epoch.as_nanos();
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (u128::from(metadata.dev()) << 64) | u128::from(metadata.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        0
+    }
+}
+
+fn codec_name(file_type: FileType, bit_depth: Option<u8>) -> String {
+    match file_type {
+        FileType::Aac => "AAC",
+        FileType::Aiff => "AIFF",
+        FileType::Ape => "APE",
+        FileType::Flac => "FLAC",
+        FileType::Mpeg => "MP3",
+        // MP4 containers only report a bit depth for lossless ALAC streams.
+        FileType::Mp4 if bit_depth.is_some() => "ALAC",
+        FileType::Mp4 => "AAC",
+        FileType::Mpc => "Musepack",
+        FileType::Opus => "Opus",
+        FileType::Vorbis => "Vorbis",
+        FileType::Speex => "Speex",
+        FileType::Wav => "WAV",
+        FileType::WavPack => "WavPack",
+        FileType::Custom(name) => name,
+        _ => "Unknown",
+    }
+    .to_string()
+}
+
+fn sniff_image_extension(data: &[u8]) -> &'static str {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "jpg"
+    } else if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+        "png"
+    } else if data.starts_with(&[0x47, 0x49, 0x46]) {
+        "gif"
+    } else if data.starts_with(&[0x42, 0x4D]) {
+        "bmp"
+    } else {
+        "jpg"
+    }
+}
+
+pub fn read_audio_metadata(path: &PathBuf, options: &MetadataOptions) -> Result<Song, String> {
+    // Tags, properties and pictures all come from this single parse.
+    match read_tagged_file(path) {
+        Ok((tagged_file, synced_lyrics)) => {
+            let tag = match tagged_file
+                .primary_tag()
+                .or_else(|| tagged_file.first_tag())
+            {
+                Some(tag) => tag,
+                None => return Err("File has no tags".to_string()),
             };
 
+            let file_metadata = fs::metadata(path).ok();
+            let cache_dir = cover_cache_dir();
+            let cover_file = cover_for(
+                path,
+                file_metadata.as_ref(),
+                tag.pictures(),
+                &options.covers,
+                &cache_dir,
+            );
+            let cover_palette = prepare_cover(&cache_dir, &cover_file);
+
+            let title_from_filename = tag.title().is_none();
             let title = tag.title().map(|s| s.to_string()).unwrap_or_else(|| {
                 path.file_stem()
                     .and_then(|s| s.to_str())
@@ -129,37 +351,101 @@ pub fn read_audio_metadata(path: &PathBuf) -> Option<Song> {
                 .artist()
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "Unknown Artist".to_string());
+            // An explicit ARTISTS list beats splitting the display credit.
+            let artist_values: Vec<&str> = match tag.get_strings(&ItemKey::TrackArtists).count() {
+                0 => tag.get_strings(&ItemKey::TrackArtist).collect(),
+                _ => tag.get_strings(&ItemKey::TrackArtists).collect(),
+            };
+            let artists = match split_artists(artist_values.iter().copied(), &options.artists) {
+                artists if artists.is_empty() => vec![artist.clone()],
+                artists => artists,
+            };
+            // Multi-valued ARTIST frames are shown together.
+            let display_artist = match tag.get_strings(&ItemKey::TrackArtist).count() {
+                0 | 1 => artist.clone(),
+                _ => tag
+                    .get_strings(&ItemKey::TrackArtist)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
             let album = tag
                 .album()
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "Unknown Album".to_string());
             let track_number = tag.track();
+            let text = |key: &ItemKey| {
+                tag.get_string(key)
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.to_string())
+            };
 
-            let duration = tagged_file.properties().duration().as_secs();
+            let properties = tagged_file.properties();
+            let duration_ms = properties.duration().as_millis() as u64;
+            let duration = duration_ms / 1000;
             let minutes = duration / 60;
             let seconds = duration % 60;
             let duration_str = format!("{}:{:02}", minutes, seconds);
-            let added_at = fs::metadata(path)
-                .ok()
+            let codec = codec_name(tagged_file.file_type(), properties.bit_depth());
+
+            let added_at = file_metadata
+                .as_ref()
                 .and_then(|meta| meta.created().ok().or_else(|| meta.modified().ok()))
                 .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
                 .map(|duration| duration.as_secs() as i64)
                 .unwrap_or(0);
+            let modified_at = file_metadata
+                .as_ref()
+                .and_then(|meta| meta.modified().ok())
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or(0);
 
-            Some(Song {
+            Ok(Song {
                 title,
-                subtitle: artist,
+                title_from_filename,
+                subtitle: display_artist,
+                artists,
                 album,
+                album_artist: text(&ItemKey::AlbumArtist),
+                compilation: matches!(
+                    text(&ItemKey::FlagCompilation).as_deref(),
+                    Some("1") | Some("true")
+                ),
                 track_number,
+                track_total: tag.track_total(),
+                disc_number: tag.disk(),
+                disc_total: tag.disk_total(),
+                year: tag.year(),
+                date: text(&ItemKey::RecordingDate).or_else(|| text(&ItemKey::ReleaseDate)),
+                genre: tag
+                    .genre()
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty()),
+                composer: text(&ItemKey::Composer),
+                comment: tag
+                    .comment()
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty()),
                 added_at,
                 duration: duration_str,
+                duration_ms,
+                bitrate: properties.audio_bitrate().or(properties.overall_bitrate()),
+                sample_rate: properties.sample_rate(),
+                bit_depth: properties.bit_depth(),
+                channels: properties.channels(),
+                track_id: compute_track_id(properties, &codec, file_metadata.as_ref()),
+                codec,
+                file_size: file_metadata.as_ref().map(|meta| meta.len()).unwrap_or(0),
+                modified_at,
                 cover: cover_file,
+                cover_palette,
+                has_lyrics: sidecar_path(path).is_some()
+                    || embedded_lyrics(&tagged_file, synced_lyrics).is_some(),
+                rating: None,
                 path: path.to_string_lossy().to_string(),
             })
         }
-        Err(e) => {
-            eprintln!("Error reading metadata for {:?}: {}", path, e);
-            None
-        }
+        Err(e) => Err(e.to_string()),
     }
 }
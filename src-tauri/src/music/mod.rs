@@ -1,5 +1,21 @@
+pub mod artists;
+pub mod artwork;
+pub mod browse;
+pub mod catalog;
+pub mod doctor;
+pub mod duplicates;
+pub mod fingerprint;
 pub mod history;
+pub mod importer;
+pub mod index;
 pub mod library;
+pub mod listing;
+pub mod lyrics;
 pub mod metadata;
 pub mod playback;
+pub mod query;
+pub mod reconcile;
 pub mod scanner;
+pub mod search;
+pub mod tag_editor;
+pub mod watcher;
@@ -1,10 +1,17 @@
+use super::lyrics::{read_lyrics, LyricLine, Lyrics, LYRICS_LINE_EVENT};
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
 use serde::Serialize;
 use std::fs::File;
 use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+// How often the active lyric line is re-checked while synced lyrics play.
+const LYRICS_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Serialize, Clone)]
 pub struct PlaybackState {
@@ -31,6 +38,14 @@ impl Default for PlaybackState {
     }
 }
 
+#[derive(Debug, Serialize, Clone)]
+pub struct LyricLineEvent {
+    pub path: String,
+    /// `None` before the first line starts.
+    pub index: Option<usize>,
+    pub line: Option<LyricLine>,
+}
+
 pub struct PlaybackService {
     tx: mpsc::Sender<PlaybackCommand>,
     snapshot: Arc<Mutex<PlaybackState>>,
@@ -64,7 +79,7 @@ enum PlaybackCommand {
 }
 
 impl PlaybackService {
-    pub fn start() -> Self {
+    pub fn start(app: AppHandle) -> Self {
         let (tx, rx) = mpsc::channel::<PlaybackCommand>();
         let snapshot = Arc::new(Mutex::new(PlaybackState::default()));
         let snapshot_for_thread = Arc::clone(&snapshot);
@@ -80,7 +95,24 @@ impl PlaybackService {
                 }
             };
 
-            while let Ok(command) = rx.recv() {
+            loop {
+                let command = if controller.tracks_lyrics() {
+                    match rx.recv_timeout(LYRICS_POLL_INTERVAL) {
+                        Ok(command) => Some(command),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                } else {
+                    match rx.recv() {
+                        Ok(command) => Some(command),
+                        Err(_) => break,
+                    }
+                };
+                let Some(command) = command else {
+                    controller.emit_lyric_line(&app);
+                    continue;
+                };
+
                 let reply = command.reply().clone();
                 let result = match command {
                     PlaybackCommand::LoadAndPlay { path, .. } => controller.load_and_play(&path),
@@ -101,6 +133,7 @@ impl PlaybackService {
                 }
 
                 let _ = reply.send(result);
+                controller.emit_lyric_line(&app);
             }
         });
 
@@ -217,6 +250,8 @@ struct PlaybackController {
     paused: bool,
     volume: f32,
     muted: bool,
+    lyrics: Option<Lyrics>,
+    lyric_line: Option<usize>,
 }
 
 impl PlaybackController {
@@ -236,12 +271,22 @@ impl PlaybackController {
             paused: true,
             volume: 0.7,
             muted: false,
+            lyrics: None,
+            lyric_line: None,
         })
     }
 
     fn load_and_play(&mut self, path: &str) -> Result<PlaybackState, String> {
         self.path = Some(path.to_string());
         self.rebuild_sink(0.0, true)?;
+        self.lyrics = match read_lyrics(Path::new(path)) {
+            Ok(lyrics) => lyrics.filter(|lyrics| lyrics.synced),
+            Err(error) => {
+                eprintln!("Failed to read lyrics for {}: {}", path, error);
+                None
+            }
+        };
+        self.lyric_line = None;
         Ok(self.state())
     }
 
@@ -370,6 +415,32 @@ impl PlaybackController {
         }
     }
 
+    fn tracks_lyrics(&self) -> bool {
+        self.lyrics.is_some() && !self.paused && !self.sink.empty()
+    }
+
+    /// Emits the synced lyric line at the current position when it differs
+    /// from the last one sent.
+    fn emit_lyric_line(&mut self, app: &AppHandle) {
+        let (Some(path), Some(lyrics)) = (&self.path, &self.lyrics) else {
+            return;
+        };
+        let index = lyrics.active_line((self.position() * 1000.0) as u64);
+        if index == self.lyric_line {
+            return;
+        }
+        self.lyric_line = index;
+
+        let event = LyricLineEvent {
+            path: path.clone(),
+            index,
+            line: index.map(|index| lyrics.lines[index].clone()),
+        };
+        if let Err(error) = app.emit(LYRICS_LINE_EVENT, &event) {
+            eprintln!("Failed to emit lyric line: {error}");
+        }
+    }
+
     fn effective_volume(&self) -> f32 {
         if self.muted {
             0.0
@@ -0,0 +1,884 @@
+use super::browse::album_artist_of;
+use super::search::{bounded_edit_distance, fold_text, tokenize, SearchQuery};
+use crate::models::models::Song;
+use serde::Serialize;
+use std::path::Path;
+
+const PHRASE_SCORE: u32 = 30;
+const FILTER_SCORE: u32 = 1;
+
+/// A query syntax error. `start` and `end` are UTF-16 offsets into the
+/// query so the frontend can highlight the offending text directly.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Composer,
+    Comment,
+    Path,
+    Format,
+    Year,
+    Duration,
+    Bitrate,
+    SampleRate,
+    Track,
+    Disc,
+}
+
+/// Every name `Field::parse` accepts, for suggestions on typos.
+const FIELD_NAMES: [&str; 17] = [
+    "title",
+    "artist",
+    "album",
+    "albumartist",
+    "genre",
+    "composer",
+    "comment",
+    "path",
+    "format",
+    "codec",
+    "year",
+    "duration",
+    "length",
+    "bitrate",
+    "samplerate",
+    "track",
+    "disc",
+];
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        let field = match name.to_lowercase().as_str() {
+            "title" => Field::Title,
+            "artist" => Field::Artist,
+            "album" => Field::Album,
+            "albumartist" => Field::AlbumArtist,
+            "genre" => Field::Genre,
+            "composer" => Field::Composer,
+            "comment" => Field::Comment,
+            "path" => Field::Path,
+            "format" | "codec" => Field::Format,
+            "year" => Field::Year,
+            "duration" | "length" => Field::Duration,
+            "bitrate" => Field::Bitrate,
+            "samplerate" => Field::SampleRate,
+            "track" => Field::Track,
+            "disc" => Field::Disc,
+            _ => return None,
+        };
+        Some(field)
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            Field::Year
+                | Field::Duration
+                | Field::Bitrate
+                | Field::SampleRate
+                | Field::Track
+                | Field::Disc
+        )
+    }
+
+    fn text(self, song: &Song) -> Option<&str> {
+        match self {
+            Field::Title => Some(&song.title),
+            Field::Artist => Some(&song.subtitle),
+            Field::Album => Some(&song.album),
+            Field::AlbumArtist => Some(album_artist_of(song)),
+            Field::Genre => song.genre.as_deref(),
+            Field::Composer => song.composer.as_deref(),
+            Field::Comment => song.comment.as_deref(),
+            Field::Path => Some(&song.path),
+            _ => None,
+        }
+    }
+
+    fn number(self, song: &Song) -> Option<f64> {
+        match self {
+            Field::Year => song.year.map(f64::from),
+            Field::Duration => Some(song.duration_ms as f64 / 1000.0),
+            Field::Bitrate => song.bitrate.map(f64::from),
+            Field::SampleRate => song.sample_rate.map(f64::from),
+            Field::Track => song.track_number.map(f64::from),
+            Field::Disc => song.disc_number.map(f64::from),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    min: Option<f64>,
+    max: Option<f64>,
+    min_exclusive: bool,
+    max_exclusive: bool,
+}
+
+impl Range {
+    fn contains(&self, value: f64) -> bool {
+        let above = match self.min {
+            Some(min) if self.min_exclusive => value > min,
+            Some(min) => value >= min,
+            None => true,
+        };
+        let below = match self.max {
+            Some(max) if self.max_exclusive => value < max,
+            Some(max) => value <= max,
+            None => true,
+        };
+        above && below
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    /// Free text, kept in both fuzzy and strict form since the same terms
+    /// match fuzzily but exclude strictly.
+    Terms(SearchQuery, SearchQuery),
+    Phrase(String),
+    Text(Field, String),
+    Number(Field, Range),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    fn score(&self, song: &Song, strict: bool) -> Option<u32> {
+        match self {
+            Expr::Terms(fuzzy, exact) => {
+                if strict {
+                    exact.score(song)
+                } else {
+                    fuzzy.score(song)
+                }
+            }
+            Expr::Phrase(phrase) => [&song.title, &song.subtitle, &song.album]
+                .iter()
+                .any(|field| contains_phrase(field, phrase))
+                .then_some(PHRASE_SCORE),
+            Expr::Text(Field::Format, value) => format_matches(song, value).then_some(FILTER_SCORE),
+            Expr::Text(field, value) => field
+                .text(song)
+                .filter(|text| contains_phrase(text, value))
+                .map(|_| FILTER_SCORE),
+            Expr::Number(field, range) => field
+                .number(song)
+                .filter(|value| range.contains(*value))
+                .map(|_| FILTER_SCORE),
+            Expr::Not(inner) => match inner.score(song, true) {
+                Some(_) => None,
+                None => Some(0),
+            },
+            Expr::And(items) => items
+                .iter()
+                .try_fold(0, |total, item| Some(total + item.score(song, strict)?)),
+            Expr::Or(items) => items
+                .iter()
+                .filter_map(|item| item.score(song, strict))
+                .max(),
+        }
+    }
+}
+
+fn terms_expr(text: &str) -> Expr {
+    Expr::Terms(SearchQuery::new(text), SearchQuery::strict(text))
+}
+
+fn contains_phrase(text: &str, phrase: &str) -> bool {
+    tokenize(text).join(" ").contains(phrase)
+}
+
+pub fn format_matches(song: &Song, value: &str) -> bool {
+    let codec = fold_text(&song.codec).replace(' ', "");
+    if value == "lossless" {
+        return matches!(
+            codec.as_str(),
+            "flac" | "alac" | "wav" | "aiff" | "ape" | "wavpack"
+        );
+    }
+    let extension = Path::new(&song.path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    codec == value || extension.as_deref() == Some(value)
+}
+
+/// A parsed search query. Free text is matched fuzzily against title,
+/// artist and album; everything else is a strict filter.
+#[derive(Debug)]
+pub struct Query {
+    expr: Option<Expr>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let tokens = lex(input)?;
+        let mut parser = Parser {
+            input,
+            tokens,
+            position: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(Self { expr: None });
+        }
+
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(parser.error("Unmatched closing parenthesis", token.span));
+        }
+        Ok(Self { expr: Some(expr) })
+    }
+
+    /// Relevance of `song`, or `None` when it does not match.
+    pub fn score(&self, song: &Song) -> Option<u32> {
+        match &self.expr {
+            Some(expr) => expr.score(song, false),
+            None => Some(0),
+        }
+    }
+
+    /// Translates the free text of this query into an FTS5 expression.
+    /// `expand` may return extra index terms to accept for a query token,
+    /// which is how typos are handled. Parts of a top-level AND that the
+    /// index cannot express are kept as residual filters; returns `None`
+    /// when nothing can be narrowed down through the index.
+    pub fn text_match(&self, expand: &dyn Fn(&str) -> Vec<String>) -> Option<TextMatch<'_>> {
+        let items = match &self.expr {
+            Some(Expr::And(items)) => items.as_slice(),
+            Some(expr) => std::slice::from_ref(expr),
+            None => return None,
+        };
+
+        let mut parts = Vec::new();
+        let mut residual = Vec::new();
+        for item in items {
+            match fts_expression(item, expand) {
+                Some(part) => parts.push(part),
+                None => residual.push(item),
+            }
+        }
+
+        (!parts.is_empty()).then(|| TextMatch {
+            expression: parts.join(" AND "),
+            residual,
+        })
+    }
+}
+
+pub struct TextMatch<'a> {
+    pub expression: String,
+    residual: Vec<&'a Expr>,
+}
+
+impl TextMatch<'_> {
+    pub fn matches(&self, song: &Song) -> bool {
+        self.residual
+            .iter()
+            .all(|expr| expr.score(song, false).is_some())
+    }
+}
+
+fn fts_expression(expr: &Expr, expand: &dyn Fn(&str) -> Vec<String>) -> Option<String> {
+    let join = |items: &[Expr], operator: &str| {
+        let parts = items
+            .iter()
+            .map(|item| fts_expression(item, expand))
+            .collect::<Option<Vec<_>>>()?;
+        Some(format!("({})", parts.join(operator)))
+    };
+
+    match expr {
+        Expr::Terms(query, _) if !query.tokens().is_empty() => {
+            let parts: Vec<String> = query
+                .tokens()
+                .iter()
+                .map(|token| {
+                    let mut options = vec![format!("\"{token}\"*")];
+                    options.extend(expand(token).iter().map(|term| format!("\"{term}\"")));
+                    format!("({})", options.join(" OR "))
+                })
+                .collect();
+            Some(parts.join(" AND "))
+        }
+        Expr::Phrase(phrase) => Some(format!("\"{phrase}\"")),
+        Expr::And(items) => join(items, " AND "),
+        Expr::Or(items) => join(items, " OR "),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug)]
+enum TokenKind {
+    Word(String),
+    Phrase(String),
+    Field {
+        field: Field,
+        name_span: Span,
+        value: String,
+        value_span: Span,
+        quoted: bool,
+    },
+    Or,
+    Minus,
+    Open,
+    Close,
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+fn utf16_offset(input: &str, byte: usize) -> usize {
+    input[..byte].encode_utf16().count()
+}
+
+fn query_error(input: &str, message: impl Into<String>, span: Span) -> QueryError {
+    QueryError {
+        message: message.into(),
+        start: utf16_offset(input, span.start),
+        end: utf16_offset(input, span.end),
+    }
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '"'
+}
+
+/// Reads a quoted string starting at the opening quote at `start`.
+/// Returns the contents and the byte offset just past the closing quote.
+fn read_quoted(input: &str, start: usize) -> Result<(String, usize), QueryError> {
+    let body = &input[start + 1..];
+    match body.find('"') {
+        Some(end) => Ok((body[..end].to_string(), start + 1 + end + 1)),
+        None => Err(query_error(
+            input,
+            "Missing closing quote",
+            Span {
+                start,
+                end: input.len(),
+            },
+        )),
+    }
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while let Some(c) = input[position..].chars().next() {
+        let start = position;
+        if c.is_whitespace() {
+            position += c.len_utf8();
+            continue;
+        }
+
+        let kind = match c {
+            '(' => {
+                position += 1;
+                TokenKind::Open
+            }
+            ')' => {
+                position += 1;
+                TokenKind::Close
+            }
+            '"' => {
+                let (text, end) = read_quoted(input, start)?;
+                position = end;
+                TokenKind::Phrase(text)
+            }
+            '-' if input[start + 1..]
+                .chars()
+                .next()
+                .is_some_and(|next| !next.is_whitespace() && next != ')') =>
+            {
+                position += 1;
+                TokenKind::Minus
+            }
+            _ => {
+                let end = input[start..]
+                    .find(is_word_end)
+                    .map_or(input.len(), |offset| start + offset);
+                let word = &input[start..end];
+                position = end;
+
+                // Only known field names start a filter, so text such as
+                // `Mission: Impossible` is still searched as words. Names
+                // close to a field are almost certainly typos.
+                if let Some((name, _)) = word.split_once(':') {
+                    if let Some(suggestion) = misspelled_field(name) {
+                        return Err(query_error(
+                            input,
+                            format!("Unknown field '{name}', did you mean '{suggestion}'?"),
+                            Span {
+                                start,
+                                end: start + name.len(),
+                            },
+                        ));
+                    }
+                }
+                match word
+                    .split_once(':')
+                    .and_then(|(name, value)| Some((name, Field::parse(name)?, value)))
+                {
+                    Some((name, field, value)) => {
+                        let name_span = Span {
+                            start,
+                            end: start + name.len(),
+                        };
+                        let value_start = name_span.end + 1;
+                        let (value, value_end, quoted) =
+                            if value.is_empty() && input[end..].starts_with('"') {
+                                let (text, quoted_end) = read_quoted(input, end)?;
+                                position = quoted_end;
+                                (text, quoted_end, true)
+                            } else {
+                                (value.to_string(), end, false)
+                            };
+                        TokenKind::Field {
+                            field,
+                            name_span,
+                            value,
+                            value_span: Span {
+                                start: value_start,
+                                end: value_end,
+                            },
+                            quoted,
+                        }
+                    }
+                    _ if word == "OR" || word == "|" => TokenKind::Or,
+                    _ => TokenKind::Word(word.to_string()),
+                }
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            span: Span {
+                start,
+                end: position,
+            },
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// The field `name` most likely misspells, if it is not a field itself.
+fn misspelled_field(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    let length = name.chars().count();
+    if length < 3 || Field::parse(&name).is_some() || !name.chars().all(char::is_alphabetic) {
+        return None;
+    }
+    let tolerance = if length <= 5 { 1 } else { 2 };
+    FIELD_NAMES
+        .iter()
+        .filter_map(|field| Some((bounded_edit_distance(&name, field, tolerance)?, *field)))
+        .min()
+        .map(|(_, field)| field)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn error(&self, message: impl Into<String>, span: Span) -> QueryError {
+        query_error(self.input, message, span)
+    }
+
+    fn end_span(&self) -> Span {
+        Span {
+            start: self.input.len(),
+            end: self.input.len(),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut items = vec![self.parse_and()?];
+
+        while let Some(token) = self.peek() {
+            if !matches!(token.kind, TokenKind::Or) {
+                break;
+            }
+            let or_span = token.span;
+            self.position += 1;
+            match self.peek().map(|token| &token.kind) {
+                None | Some(TokenKind::Or) | Some(TokenKind::Close) => {
+                    return Err(self.error("Expected a search term after OR", or_span));
+                }
+                _ => items.push(self.parse_and()?),
+            }
+        }
+
+        Ok(if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Expr::Or(items)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut items = Vec::new();
+        // Consecutive plain words are scored together so multi-word free
+        // text still gets the phrase bonus from `SearchQuery`.
+        let mut words: Vec<String> = Vec::new();
+
+        while let Some(token) = self.peek() {
+            match &token.kind {
+                TokenKind::Or | TokenKind::Close => break,
+                TokenKind::Word(word) if word == "AND" => {
+                    self.position += 1;
+                }
+                TokenKind::Word(word) => {
+                    words.push(word.clone());
+                    self.position += 1;
+                }
+                _ => {
+                    if !words.is_empty() {
+                        items.push(terms_expr(&words.join(" ")));
+                        words.clear();
+                    }
+                    items.push(self.parse_unary()?);
+                }
+            }
+        }
+        if !words.is_empty() {
+            items.push(terms_expr(&words.join(" ")));
+        }
+
+        match items.len() {
+            0 => {
+                let span = self.peek().map_or(self.end_span(), |token| token.span);
+                Err(self.error("Expected a search term", span))
+            }
+            1 => Ok(items.pop().unwrap()),
+            _ => Ok(Expr::And(items)),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        let token = &self.tokens[self.position];
+        if !matches!(token.kind, TokenKind::Minus) {
+            return self.parse_atom();
+        }
+
+        let minus_span = token.span;
+        self.position += 1;
+        match self.peek().map(|token| &token.kind) {
+            None | Some(TokenKind::Or) | Some(TokenKind::Close) | Some(TokenKind::Minus) => {
+                Err(self.error("Expected a term to exclude after '-'", minus_span))
+            }
+            _ => Ok(Expr::Not(Box::new(self.parse_atom()?))),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, QueryError> {
+        let token = &self.tokens[self.position];
+        let span = token.span;
+        self.position += 1;
+
+        match &token.kind {
+            TokenKind::Word(word) => Ok(terms_expr(word)),
+            TokenKind::Phrase(text) => {
+                let phrase = tokenize(text).join(" ");
+                if phrase.is_empty() {
+                    return Err(self.error("Empty quoted phrase", span));
+                }
+                Ok(Expr::Phrase(phrase))
+            }
+            TokenKind::Field {
+                field,
+                name_span,
+                value,
+                value_span,
+                quoted,
+            } => self.parse_field(*field, *name_span, value, *value_span, *quoted),
+            TokenKind::Open => {
+                if matches!(self.peek().map(|token| &token.kind), Some(TokenKind::Close)) {
+                    return Err(self.error("Empty parentheses", span));
+                }
+                let expr = self.parse_or()?;
+                match self.peek().map(|token| &token.kind) {
+                    Some(TokenKind::Close) => {
+                        self.position += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(self.error("Missing closing parenthesis", span)),
+                }
+            }
+            TokenKind::Close => Err(self.error("Unmatched closing parenthesis", span)),
+            TokenKind::Or => Err(self.error("Expected a search term before OR", span)),
+            TokenKind::Minus => Err(self.error("Expected a term to exclude after '-'", span)),
+        }
+    }
+
+    fn parse_field(
+        &self,
+        field: Field,
+        name_span: Span,
+        value: &str,
+        value_span: Span,
+        quoted: bool,
+    ) -> Result<Expr, QueryError> {
+        let name = &self.input[name_span.start..name_span.end];
+        if value.trim().is_empty() {
+            let span = if quoted {
+                value_span
+            } else {
+                Span {
+                    start: name_span.start,
+                    end: value_span.end,
+                }
+            };
+            return Err(self.error(format!("Missing value for '{name}'"), span));
+        }
+
+        if !field.is_numeric() {
+            let value = tokenize(value).join(" ");
+            if value.is_empty() {
+                return Err(self.error(format!("Missing value for '{name}'"), value_span));
+            }
+            return Ok(Expr::Text(field, value));
+        }
+
+        let parse_value = |text: &str| parse_number(field, text.trim());
+        let invalid = || {
+            let hint = match field {
+                Field::Duration => "a duration like 3:30, 90s or 10m",
+                Field::Bitrate => "a bitrate in kbps",
+                Field::SampleRate => "a sample rate like 44100 or 44.1khz",
+                _ => "a number",
+            };
+            self.error(format!("Expected {hint} for '{name}'"), value_span)
+        };
+
+        let range = if let Some((min, max)) = value.split_once("..") {
+            if min.is_empty() && max.is_empty() {
+                return Err(invalid());
+            }
+            let min = (!min.is_empty())
+                .then(|| parse_value(min).ok_or_else(invalid))
+                .transpose()?;
+            let max = (!max.is_empty())
+                .then(|| parse_value(max).ok_or_else(invalid))
+                .transpose()?;
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    return Err(self.error("Range start is after its end", value_span));
+                }
+            }
+            Range {
+                min,
+                max,
+                min_exclusive: false,
+                max_exclusive: false,
+            }
+        } else {
+            let (operator, number) = [">=", "<=", ">", "<", "="]
+                .iter()
+                .find_map(|operator| {
+                    value
+                        .strip_prefix(operator)
+                        .map(|number| (*operator, number))
+                })
+                .unwrap_or(("=", value));
+            let number = parse_value(number).ok_or_else(invalid)?;
+            let (min, max) = match operator {
+                ">=" | ">" => (Some(number), None),
+                "<=" | "<" => (None, Some(number)),
+                _ => exact_bounds(field, number),
+            };
+            Range {
+                min,
+                max,
+                min_exclusive: operator == ">",
+                max_exclusive: operator == "<" || (operator == "=" && max != min),
+            }
+        };
+
+        Ok(Expr::Number(field, range))
+    }
+}
+
+/// Bounds for an `=` comparison. Durations are stored in milliseconds but
+/// typed in seconds, so `duration:3:30` matches the whole 3:30 second.
+fn exact_bounds(field: Field, number: f64) -> (Option<f64>, Option<f64>) {
+    match field {
+        Field::Duration => (Some(number), Some(number + 1.0)),
+        _ => (Some(number), Some(number)),
+    }
+}
+
+fn parse_number(field: Field, text: &str) -> Option<f64> {
+    let lower = text.to_lowercase();
+    let number = match field {
+        Field::Duration => parse_duration(&lower)?,
+        Field::Bitrate => lower
+            .trim_end_matches("kbps")
+            .trim_end_matches('k')
+            .parse()
+            .ok()?,
+        Field::SampleRate => match lower.strip_suffix("khz") {
+            Some(khz) => khz.parse::<f64>().ok()? * 1000.0,
+            None => lower.trim_end_matches("hz").parse().ok()?,
+        },
+        _ => lower.parse::<u32>().ok()? as f64,
+    };
+    (number.is_finite() && number >= 0.0).then_some(number)
+}
+
+/// Parses `3:30`, `1:02:03`, `90s`, `10m`, `1h30m` or plain seconds.
+fn parse_duration(text: &str) -> Option<f64> {
+    if text.contains(':') {
+        return text.split(':').try_fold(0.0, |total, part| {
+            let part: u32 = part.parse().ok()?;
+            Some(total * 60.0 + part as f64)
+        });
+    }
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Some(seconds);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let value: f64 = number.parse().ok()?;
+        number.clear();
+        total += value
+            * match c {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => return None,
+            };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, artist: &str, year: u32) -> Song {
+        Song {
+            title: title.to_string(),
+            subtitle: artist.to_string(),
+            artists: vec![artist.to_string()],
+            album: "Album".to_string(),
+            year: Some(year),
+            codec: "FLAC".to_string(),
+            path: format!("/music/{artist}/{title}.flac"),
+            ..Song::default()
+        }
+    }
+
+    fn matches(query: &str, song: &Song) -> bool {
+        Query::parse(query).unwrap().score(song).is_some()
+    }
+
+    #[test]
+    fn colons_in_text_are_searched_as_words() {
+        let mission = song("Mission: Impossible", "Lalo Schifrin", 1967);
+        assert!(matches("Mission: Impossible", &mission));
+        assert!(Query::parse("Re:Stacks").is_ok());
+        assert!(Query::parse("Mission:Impossible").is_ok());
+        assert!(!matches("Re:Stacks", &mission));
+    }
+
+    #[test]
+    fn field_names_are_case_insensitive() {
+        let help = song("Help", "The Beatles", 1965);
+        assert!(matches("ARTIST:beatles", &help));
+        assert!(!matches("artist:stones", &help));
+        assert!(matches("codec:flac", &help));
+        assert!(matches("format:lossless", &help));
+    }
+
+    #[test]
+    fn quoted_field_values() {
+        let help = song("Help", "The Beatles", 1965);
+        assert!(matches("artist:\"the beatles\"", &help));
+        assert!(!matches("artist:\"beatles the\"", &help));
+    }
+
+    #[test]
+    fn numeric_ranges() {
+        let help = song("Help", "The Beatles", 1965);
+        assert!(matches("year:1965", &help));
+        assert!(matches("year:1960..1969", &help));
+        assert!(matches("year:..1965", &help));
+        assert!(!matches("year:1966..", &help));
+    }
+
+    #[test]
+    fn negation_and_alternatives() {
+        let help = song("Help", "The Beatles", 1965);
+        assert!(!matches("help -artist:beatles", &help));
+        assert!(matches("help -artist:stones", &help));
+        assert!(matches("artist:stones OR year:1965", &help));
+        assert!(matches("(artist:stones | artist:beatles) help", &help));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = Query::parse("year:abc").unwrap_err();
+        assert_eq!((error.start, error.end), (5, 8));
+
+        let error = Query::parse("help artst:beatles").unwrap_err();
+        assert_eq!(
+            error.message,
+            "Unknown field 'artst', did you mean 'artist'?"
+        );
+        assert_eq!((error.start, error.end), (5, 10));
+
+        let error = Query::parse("title:").unwrap_err();
+        assert!(error.message.contains("Missing value"));
+
+        let error = Query::parse("\"open").unwrap_err();
+        assert_eq!(error.message, "Missing closing quote");
+
+        let error = Query::parse("help)").unwrap_err();
+        assert_eq!(error.message, "Unmatched closing parenthesis");
+        assert_eq!((error.start, error.end), (4, 5));
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert!(matches("   ", &song("Help", "The Beatles", 1965)));
+    }
+}
@@ -0,0 +1,343 @@
+use super::browse::album_artist_of;
+use super::catalog::{CatalogFingerprint, LibraryCatalog};
+use super::fingerprint::FingerprintIndex;
+use super::history::ListeningHistoryStore;
+use super::library::{MovedTrack, MusicLibrary};
+use super::search::tokenize;
+use crate::models::models::Song;
+use crate::playlists::store::PlaylistStore;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+const MAX_CANDIDATES: usize = 5;
+const SAME_TRACK_ID_SCORE: u32 = 100;
+/// Same audio, e.g. after re-encoding, which changes the track id.
+const SAME_FINGERPRINT_SCORE: u32 = 90;
+const TITLE_SCORE: u32 = 50;
+const ALBUM_SCORE: u32 = 25;
+const ARTIST_SCORE: u32 = 20;
+const FILE_NAME_SCORE: u32 = 5;
+
+/// A playlist or history entry whose file is neither indexed nor on disk.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingTrack {
+    pub path: String,
+    pub track_id: Option<String>,
+    pub playlists: Vec<String>,
+    pub play_count: usize,
+    pub last_played_at: Option<i64>,
+    /// Best match first.
+    pub candidates: Vec<RelocationCandidate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelocationCandidate {
+    pub path: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefixRewrite {
+    pub moved: Vec<MovedTrack>,
+    /// Rewritten paths that point at an indexed track.
+    pub resolved: usize,
+    pub applied: bool,
+}
+
+/// Lists dead playlist and history entries with the indexed tracks they
+/// most likely moved to.
+pub fn find_missing_tracks(
+    library: &MusicLibrary,
+    playlists: &PlaylistStore,
+    history: &ListeningHistoryStore,
+    catalog: &LibraryCatalog,
+) -> Result<Vec<MissingTrack>, String> {
+    let fingerprints = catalog.fingerprints()?;
+    let library = library.library.lock().unwrap();
+    let mut missing: BTreeMap<String, MissingTrack> = BTreeMap::new();
+
+    for record in playlists.track_references()? {
+        if is_missing(&library, &record.track_path) {
+            missing_entry(&mut missing, &record.track_path, record.track_id.as_ref())
+                .playlists
+                .push(record.playlist_slug);
+        }
+    }
+    for record in history.track_references() {
+        if is_missing(&library, &record.track_path) {
+            let track = missing_entry(&mut missing, &record.track_path, record.track_id.as_ref());
+            track.play_count = record.play_count;
+            track.last_played_at = Some(record.last_played_at);
+        }
+    }
+
+    let by_title = titles(&library);
+    let index = FingerprintIndex::new(
+        fingerprints
+            .iter()
+            .filter(|entry| library.contains_key(&entry.path))
+            .map(|entry| (entry.path.as_str(), entry.fingerprint.as_slice())),
+    );
+    let mut missing: Vec<MissingTrack> = missing.into_values().collect();
+    for track in &mut missing {
+        let mut candidates = candidates(track, &library, &by_title);
+        if let Some(fingerprint) = missing_fingerprint(track, &fingerprints) {
+            add_fingerprint_matches(&mut candidates, index.matches(fingerprint), &library);
+        }
+        track.candidates = candidates;
+    }
+    Ok(missing)
+}
+
+fn missing_entry<'a>(
+    missing: &'a mut BTreeMap<String, MissingTrack>,
+    path: &str,
+    track_id: Option<&String>,
+) -> &'a mut MissingTrack {
+    let track = missing
+        .entry(path.to_string())
+        .or_insert_with(|| MissingTrack {
+            path: path.to_string(),
+            track_id: None,
+            playlists: Vec::new(),
+            play_count: 0,
+            last_played_at: None,
+            candidates: Vec::new(),
+        });
+    if track.track_id.is_none() {
+        track.track_id = track_id.cloned();
+    }
+    track
+}
+
+/// The fingerprint analysed while the file was still there, found by path
+/// or by the track id the entry was saved with.
+fn missing_fingerprint<'a>(
+    track: &MissingTrack,
+    fingerprints: &'a [CatalogFingerprint],
+) -> Option<&'a [u32]> {
+    fingerprints
+        .iter()
+        .find(|entry| entry.path == track.path)
+        .or_else(|| {
+            let track_id = track.track_id.as_ref()?;
+            fingerprints
+                .iter()
+                .find(|entry| &entry.track_id == track_id)
+        })
+        .map(|entry| entry.fingerprint.as_slice())
+}
+
+fn add_fingerprint_matches(
+    candidates: &mut Vec<RelocationCandidate>,
+    matches: Vec<(&str, f64)>,
+    library: &HashMap<String, Song>,
+) {
+    for (path, _) in matches {
+        match candidates
+            .iter_mut()
+            .find(|candidate| candidate.path == path)
+        {
+            Some(candidate) => candidate.score = candidate.score.max(SAME_FINGERPRINT_SCORE),
+            None => {
+                let song = &library[path];
+                candidates.push(RelocationCandidate {
+                    path: song.path.clone(),
+                    title: song.title.clone(),
+                    artist: song.subtitle.clone(),
+                    album: song.album.clone(),
+                    score: SAME_FINGERPRINT_SCORE,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+    candidates.truncate(MAX_CANDIDATES);
+}
+
+fn is_missing(library: &HashMap<String, Song>, path: &str) -> bool {
+    !library.contains_key(path) && !Path::new(path).exists()
+}
+
+fn normalized(text: &str) -> String {
+    tokenize(text).join(" ")
+}
+
+fn titles(library: &HashMap<String, Song>) -> HashMap<String, Vec<&Song>> {
+    let mut by_title: HashMap<String, Vec<&Song>> = HashMap::new();
+    for song in library.values() {
+        by_title
+            .entry(normalized(&song.title))
+            .or_default()
+            .push(song);
+    }
+    by_title
+}
+
+/// Tags are gone with the file, so they are guessed from the usual
+/// `Artist/Album/01 Title.ext` layout of the old path.
+fn candidates(
+    track: &MissingTrack,
+    library: &HashMap<String, Song>,
+    by_title: &HashMap<String, Vec<&Song>>,
+) -> Vec<RelocationCandidate> {
+    let path = Path::new(&track.path);
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase());
+    let folder = |levels: usize| {
+        path.ancestors()
+            .nth(levels)
+            .and_then(Path::file_name)
+            .map(|name| normalized(&name.to_string_lossy()))
+    };
+    let album = folder(1);
+    let artist = folder(2);
+
+    let mut title_tokens = path
+        .file_stem()
+        .map(|stem| tokenize(&stem.to_string_lossy()))
+        .unwrap_or_default();
+    // Drops leading track and disc numbers such as "01" or "1-02".
+    while title_tokens.len() > 1
+        && title_tokens[0]
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '-')
+    {
+        title_tokens.remove(0);
+    }
+    let title = title_tokens.join(" ");
+
+    let mut scored: HashMap<&str, (u32, &Song)> = HashMap::new();
+    if let Some(song) = track
+        .track_id
+        .as_ref()
+        .and_then(|id| library.values().find(|song| &song.track_id == id))
+    {
+        scored.insert(&song.path, (SAME_TRACK_ID_SCORE, song));
+    }
+
+    for song in by_title.get(&title).into_iter().flatten() {
+        let mut score = TITLE_SCORE;
+        if album.as_deref() == Some(normalized(&song.album).as_str()) {
+            score += ALBUM_SCORE;
+        }
+        let artist_matches = artist.as_ref().is_some_and(|artist| {
+            song.artists
+                .iter()
+                .map(String::as_str)
+                .chain([album_artist_of(song)])
+                .any(|name| normalized(name) == *artist)
+        });
+        if artist_matches {
+            score += ARTIST_SCORE;
+        }
+        let song_file_name = Path::new(&song.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase());
+        if file_name.is_some() && song_file_name == file_name {
+            score += FILE_NAME_SCORE;
+        }
+
+        let best = scored.entry(&song.path).or_insert((score, song));
+        best.0 = best.0.max(score);
+    }
+
+    let mut candidates: Vec<RelocationCandidate> = scored
+        .into_values()
+        .map(|(score, song)| RelocationCandidate {
+            path: song.path.clone(),
+            title: song.title.clone(),
+            artist: song.subtitle.clone(),
+            album: song.album.clone(),
+            score,
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+/// Points playlist and history entries at the chosen files. Every target
+/// must be indexed.
+pub fn relocate_tracks(
+    library: &MusicLibrary,
+    playlists: &PlaylistStore,
+    history: &ListeningHistoryStore,
+    moves: &[MovedTrack],
+) -> Result<usize, String> {
+    if let Some(track) = moves
+        .iter()
+        .find(|track| library.by_path(&track.to).is_none())
+    {
+        return Err(format!("Track is not in the library: {}", track.to));
+    }
+    playlists.move_track_paths_with_history(moves, history)
+}
+
+/// Replaces the folder prefix `from` with `to` in every dead playlist and
+/// history entry, e.g. after a drive was mounted elsewhere. Whole path
+/// components are matched, so `/mnt/old` leaves `/mnt/older` alone, and
+/// entries whose files are still there are never touched.
+pub fn rewrite_path_prefix(
+    library: &MusicLibrary,
+    playlists: &PlaylistStore,
+    history: &ListeningHistoryStore,
+    from: &str,
+    to: &str,
+    dry_run: bool,
+) -> Result<PrefixRewrite, String> {
+    let from = PathBuf::from(from.trim());
+    let to = PathBuf::from(to.trim());
+    if from.as_os_str().is_empty() || to.as_os_str().is_empty() {
+        return Err("Both folders are required".to_string());
+    }
+
+    let mut paths: Vec<String> = playlists
+        .track_references()?
+        .into_iter()
+        .map(|record| record.track_path)
+        .chain(
+            history
+                .track_references()
+                .into_iter()
+                .map(|record| record.track_path),
+        )
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let moved: Vec<MovedTrack> = {
+        let songs = library.library.lock().unwrap();
+        paths
+            .into_iter()
+            .filter(|path| is_missing(&songs, path))
+            .filter_map(|path| {
+                let relative = Path::new(&path).strip_prefix(&from).ok()?;
+                let new_path = to.join(relative).to_string_lossy().to_string();
+                (new_path != path).then_some(MovedTrack {
+                    from: path,
+                    to: new_path,
+                })
+            })
+            .collect()
+    };
+    let resolved = moved
+        .iter()
+        .filter(|track| library.by_path(&track.to).is_some())
+        .count();
+
+    if !dry_run && !moved.is_empty() {
+        playlists.move_track_paths_with_history(&moved, history)?;
+    }
+
+    Ok(PrefixRewrite {
+        moved,
+        resolved,
+        applied: !dry_run,
+    })
+}
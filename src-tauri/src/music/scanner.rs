@@ -1,94 +1,435 @@
-use super::metadata::read_audio_metadata;
-use crate::models::models::Song;
+use super::artists::ArtistOptions;
+use super::artwork::cover_hash;
+use super::lyrics::sidecar_path;
+use super::metadata::{cover_cache_dir, find_folder_cover, CoverOptions, MetadataOptions};
+use crate::config::config::{load_config, Config};
+use crate::models::models::{Song, SymlinkPolicy};
+use chrono::Utc;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
-
-pub fn get_music_directory() -> PathBuf {
-    dirs::audio_dir()
-        .or_else(|| dirs::home_dir().map(|p| p.join("Music")))
-        .unwrap_or_else(|| {
-            #[cfg(target_os = "windows")]
-            {
-                PathBuf::from("C:\\Users\\Public\\Music")
-            }
-            #[cfg(target_os = "macos")]
-            {
-                PathBuf::from("/Users/Shared/Music")
-            }
-            #[cfg(target_os = "linux")]
-            {
-                PathBuf::from("/var/lib/flatpak/exports/share/music")
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub struct ScanOptions {
+    pub roots: Vec<PathBuf>,
+    pub excluded: GlobSet,
+    pub min_duration_seconds: u64,
+    pub metadata: MetadataOptions,
+    pub symlinks: SymlinkPolicy,
+    pub include_hidden: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanIssueKind {
+    UnreadableDirectory,
+    UnreadableFile,
+    UnparseableFile,
+    /// A link leading back into a directory that was already scanned.
+    RepeatedDirectory,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanIssue {
+    pub kind: ScanIssueKind,
+    pub path: String,
+    pub error: String,
+}
+
+/// Everything the last full scan could not read, for the UI to list.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    pub finished_at: Option<i64>,
+    pub directories: usize,
+    pub files: usize,
+    pub issues: Vec<ScanIssue>,
+}
+
+impl ScanReport {
+    pub fn push(&mut self, kind: ScanIssueKind, path: &Path, error: impl ToString) {
+        self.issues.push(ScanIssue {
+            kind,
+            path: path.to_string_lossy().to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn finish(&mut self) {
+        self.finished_at = Some(Utc::now().timestamp());
+    }
+}
+
+impl ScanOptions {
+    pub fn load() -> Self {
+        Self::from_config(&load_config())
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &config.excluded_patterns {
+            match GlobBuilder::new(pattern).literal_separator(true).build() {
+                Ok(glob) => {
+                    builder.add(glob);
+                }
+                Err(e) => eprintln!("Ignoring invalid exclusion pattern {:?}: {}", pattern, e),
             }
+        }
+
+        Self {
+            roots: library_roots(config),
+            excluded: builder.build().unwrap_or_else(|_| GlobSet::empty()),
+            min_duration_seconds: config.min_duration_seconds,
+            metadata: MetadataOptions {
+                covers: CoverOptions {
+                    folder_names: config.folder_cover_names.clone(),
+                    priority: config.cover_priority,
+                },
+                artists: ArtistOptions {
+                    separators: config.artist_separators.clone(),
+                    exceptions: config.artist_separator_exceptions.clone(),
+                },
+            },
+            symlinks: config.symlink_policy,
+            include_hidden: config.scan_hidden,
+        }
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self.excluded.is_match(path) {
+            return true;
+        }
+
+        self.roots.iter().any(|root| {
+            path.strip_prefix(root)
+                .map(|relative| {
+                    self.excluded.is_match(relative)
+                        || (!self.include_hidden && has_hidden_component(relative))
+                })
+                .unwrap_or(false)
         })
+    }
+
+    pub fn includes(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root)) && !self.is_excluded(path)
+    }
+}
+
+pub fn library_roots(config: &Config) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for root in &config.library_roots {
+        let trimmed = root.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let path = PathBuf::from(trimmed);
+        if !roots.contains(&path) {
+            roots.push(path);
+        }
+    }
+    roots
 }
 
-pub fn index_music_files() -> HashMap<String, Song> {
-    let music_dir = get_music_directory();
-    let mut music_library = HashMap::new();
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub inode: u64,
+    /// Modification time of the `.lrc` sidecar, so editing it rescans the track.
+    pub lyrics_modified: Option<SystemTime>,
+    /// The folder image the track's artwork would come from and its
+    /// modification time, so adding, replacing or renaming that image
+    /// rescans the tracks next to it while other files in the folder don't.
+    pub folder_cover: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+pub fn file_stamp(path: &Path, folder_cover_names: &[String]) -> Option<FileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+
+    #[cfg(unix)]
+    let inode = {
+        use std::os::unix::fs::MetadataExt;
+        metadata.ino()
+    };
+    #[cfg(not(unix))]
+    let inode = 0;
 
-    println!("Indexing music from: {:?}", music_dir);
+    Some(FileStamp {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        inode,
+        lyrics_modified: sidecar_path(path)
+            .and_then(|sidecar| fs::metadata(sidecar).ok())
+            .and_then(|metadata| metadata.modified().ok()),
+        folder_cover: find_folder_cover(path, folder_cover_names).map(|image| {
+            let modified = fs::metadata(&image)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            (image, modified)
+        }),
+    })
+}
 
-    if !music_dir.exists() {
-        eprintln!("Music directory does not exist: {:?}", music_dir);
-        return music_library;
+pub fn is_audio_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => matches!(
+            extension.to_string_lossy().to_lowercase().as_str(),
+            "mp3" | "flac" | "alac" | "wav" | "m4a" | "ogg" | "aac"
+        ),
+        None => false,
     }
+}
 
-    index_directory(&music_dir, &mut music_library);
+pub fn scan_music_files(
+    options: &ScanOptions,
+    report: &mut ScanReport,
+) -> Vec<(PathBuf, FileStamp)> {
+    let mut walker = Walker::new(options, report);
 
-    if let Err(e) = cleanup_unused_covers(&music_library) {
-        eprintln!("Error cleaning up unused covers: {}", e);
+    for music_dir in &options.roots {
+        println!("Scanning music from: {:?}", music_dir);
+
+        if !music_dir.exists() {
+            eprintln!("Music directory does not exist: {:?}", music_dir);
+            walker.report.push(
+                ScanIssueKind::UnreadableDirectory,
+                music_dir,
+                "Library folder does not exist",
+            );
+            continue;
+        }
+
+        walker.walk(music_dir, false);
     }
+    walker.walk_links();
 
-    println!("Indexed {} songs", music_library.len());
-    music_library
+    walker.files
+}
+
+pub fn scan_directory(
+    dir: &Path,
+    options: &ScanOptions,
+    report: &mut ScanReport,
+) -> Vec<(PathBuf, FileStamp)> {
+    let mut walker = Walker::new(options, report);
+    walker.walk(dir, false);
+    walker.walk_links();
+    walker.files
+}
+
+// Directories are identified by device and inode where the platform has
+// them, and by their canonical path elsewhere.
+#[cfg(unix)]
+type DirectoryId = (u64, u64);
+#[cfg(not(unix))]
+type DirectoryId = PathBuf;
+
+struct Walker<'a> {
+    options: &'a ScanOptions,
+    report: &'a mut ScanReport,
+    canonical_roots: Vec<PathBuf>,
+    visited: HashMap<DirectoryId, PathBuf>,
+    linked_dirs: Vec<PathBuf>,
+    files: Vec<(PathBuf, FileStamp)>,
 }
 
-fn index_directory(dir: &PathBuf, music_library: &mut HashMap<String, Song>) {
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
+impl<'a> Walker<'a> {
+    fn new(options: &'a ScanOptions, report: &'a mut ScanReport) -> Self {
+        Self {
+            options,
+            report,
+            canonical_roots: options
+                .roots
+                .iter()
+                .filter_map(|root| fs::canonicalize(root).ok())
+                .collect(),
+            visited: HashMap::new(),
+            linked_dirs: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Every directory is entered once, so a link pointing back up the tree
+    /// cannot recurse forever.
+    fn walk(&mut self, dir: &Path, via_link: bool) {
+        let metadata = match fs::metadata(dir) {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                self.report
+                    .push(ScanIssueKind::UnreadableDirectory, dir, error);
+                return;
+            }
+        };
+        if let Some(id) = directory_id(dir, &metadata) {
+            if let Some(first) = self.visited.get(&id) {
+                // Overlapping roots reach the same folders without a link;
+                // only links are worth reporting.
+                if via_link {
+                    let error = if dir.starts_with(first) {
+                        format!("Link loops back to {}", first.display())
+                    } else {
+                        format!("Already scanned as {}", first.display())
+                    };
+                    self.report
+                        .push(ScanIssueKind::RepeatedDirectory, dir, error);
+                }
+                return;
+            }
+            self.visited.insert(id, dir.to_path_buf());
+        }
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                eprintln!("Cannot read directory {:?}: {}", dir, error);
+                self.report
+                    .push(ScanIssueKind::UnreadableDirectory, dir, error);
+                return;
+            }
+        };
+        self.report.directories += 1;
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    self.report
+                        .push(ScanIssueKind::UnreadableDirectory, dir, error);
+                    continue;
+                }
+            };
             let path = entry.path();
 
-            if path.is_dir() {
-                index_directory(&path, music_library);
-            } else {
-                if let Some(extension) = path.extension() {
-                    let ext = extension.to_string_lossy().to_lowercase();
-                    if matches!(
-                        ext.as_str(),
-                        "mp3" | "flac" | "alac" | "wav" | "m4a" | "ogg" | "aac"
-                    ) {
-                        if let Some(song) = read_audio_metadata(&path) {
-                            music_library.insert(path.to_string_lossy().to_string(), song);
-                        }
+            if self.options.is_excluded(&path) {
+                continue;
+            }
+
+            let is_link = entry
+                .file_type()
+                .is_ok_and(|file_type| file_type.is_symlink());
+            if is_link && !self.follows_link(&path) {
+                continue;
+            }
+
+            // Follows the link, so broken links end up here as well.
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(error) => {
+                    let kind = if is_audio_file(&path) || is_link {
+                        ScanIssueKind::UnreadableFile
+                    } else {
+                        ScanIssueKind::UnreadableDirectory
+                    };
+                    self.report.push(kind, &path, error);
+                    continue;
+                }
+            };
+            if !self.options.include_hidden && is_hidden(&path, &metadata) {
+                continue;
+            }
+
+            if metadata.is_dir() && is_link {
+                self.linked_dirs.push(path);
+            } else if metadata.is_dir() {
+                self.walk(&path, false);
+            } else if is_audio_file(&path) {
+                match file_stamp(&path, &self.options.metadata.covers.folder_names) {
+                    Some(stamp) => {
+                        self.report.files += 1;
+                        self.files.push((path, stamp));
                     }
+                    None => self.report.push(
+                        ScanIssueKind::UnreadableFile,
+                        &path,
+                        "Could not read file metadata",
+                    ),
                 }
             }
         }
     }
-}
 
-fn cleanup_unused_covers(music_library: &HashMap<String, Song>) -> std::io::Result<()> {
-    let cache_dir = match dirs::cache_dir() {
-        Some(mut cache) => {
-            cache.push("me.wdkq.rift");
-            cache.push("covers");
-            cache
+    /// Linked folders are entered after the real tree, so a folder reachable
+    /// both ways is indexed under its real path.
+    fn walk_links(&mut self) {
+        while !self.linked_dirs.is_empty() {
+            for dir in std::mem::take(&mut self.linked_dirs) {
+                self.walk(&dir, true);
+            }
         }
-        None => PathBuf::from("./.cover_cache"),
-    };
+    }
 
-    if !cache_dir.exists() {
-        return Ok(());
+    fn follows_link(&self, path: &Path) -> bool {
+        match self.options.symlinks {
+            SymlinkPolicy::Ignore => false,
+            SymlinkPolicy::Follow => true,
+            SymlinkPolicy::WithinRoots => fs::canonicalize(path).is_ok_and(|target| {
+                self.canonical_roots
+                    .iter()
+                    .any(|root| target.starts_with(root))
+            }),
+        }
     }
+}
 
-    let mut used_covers = HashSet::new();
+fn directory_id(path: &Path, metadata: &fs::Metadata) -> Option<DirectoryId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = path;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        fs::canonicalize(path).ok()
+    }
+}
 
-    for song in music_library.values() {
-        if !song.cover.is_empty() {
-            used_covers.insert(song.cover.clone());
+fn is_hidden(path: &Path, metadata: &fs::Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 {
+            return true;
         }
     }
+    #[cfg(not(windows))]
+    let _ = metadata;
+
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+fn has_hidden_component(relative: &Path) -> bool {
+    relative
+        .components()
+        .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Hashes of the cached covers `music_library` uses. Thumbnails and
+/// palettes share their cover's hash and go with it.
+pub fn used_cover_hashes(music_library: &HashMap<String, Song>) -> HashSet<String> {
+    music_library
+        .values()
+        .filter(|song| !song.cover.is_empty())
+        .map(|song| cover_hash(&song.cover).to_string())
+        .collect()
+}
+
+/// Deletes cached covers whose hash is not in `used_covers`. Files written
+/// at or after `written_before` are kept, as their tracks may not be
+/// indexed yet.
+pub fn cleanup_unused_covers(
+    used_covers: &HashSet<String>,
+    written_before: SystemTime,
+) -> std::io::Result<()> {
+    let cache_dir = cover_cache_dir();
+
+    if !cache_dir.exists() {
+        return Ok(());
+    }
 
     let entries = fs::read_dir(&cache_dir)?;
 
@@ -100,7 +441,11 @@ fn cleanup_unused_covers(music_library: &HashMap<String, Song>) -> std::io::Resu
             if let Some(file_name) = path.file_name() {
                 let file_name_str = file_name.to_string_lossy().to_string();
 
-                if !used_covers.contains(&file_name_str) {
+                let recent = entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| modified >= written_before);
+                if !recent && !used_covers.contains(cover_hash(&file_name_str)) {
                     fs::remove_file(&path)?;
                 }
             }
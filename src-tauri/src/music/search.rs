@@ -0,0 +1,439 @@
+use super::index::SearchIndex;
+use super::query::Query;
+use crate::models::models::Song;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 50;
+// Keeps the index expression small for short tokens that occur inside
+// thousands of words.
+const MAX_TERM_EXPANSIONS: usize = 64;
+
+const TITLE_WEIGHT: u32 = 3;
+const ARTIST_WEIGHT: u32 = 2;
+const ALBUM_WEIGHT: u32 = 1;
+
+const EXACT_SCORE: u32 = 10;
+const PREFIX_SCORE: u32 = 7;
+const SUBSTRING_SCORE: u32 = 4;
+const FUZZY_SCORE: u32 = 3;
+const PHRASE_BONUS: u32 = 20;
+
+/// Lowercases `text`, strips accents ("Beyoncé" -> "beyonce") and turns
+/// punctuation into spaces so it can be split into tokens.
+pub fn fold_text(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for c in text.nfkd() {
+        if is_combining_mark(c) {
+            continue;
+        }
+        // Letters that do not decompose into a base letter plus a mark.
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' | 'Æ' => folded.push_str("ae"),
+            'œ' | 'Œ' => folded.push_str("oe"),
+            'ø' | 'Ø' => folded.push('o'),
+            'đ' | 'Đ' | 'ð' | 'Ð' => folded.push('d'),
+            'ł' | 'Ł' => folded.push('l'),
+            'þ' | 'Þ' => folded.push_str("th"),
+            'ı' => folded.push('i'),
+            c if c.is_alphanumeric() => folded.extend(c.to_lowercase()),
+            '\'' | '’' => {}
+            _ => folded.push(' '),
+        }
+    }
+
+    folded
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    fold_text(text)
+        .split_whitespace()
+        .map(|token| token.to_string())
+        .collect()
+}
+
+fn typo_tolerance(token: &str) -> usize {
+    match token.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Levenshtein distance, giving up as soon as it exceeds `max`.
+pub fn bounded_edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        let mut row_min = current[0];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            row_min = row_min.min(current[j + 1]);
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    let distance = previous[b.len()];
+    (distance <= max).then_some(distance)
+}
+
+fn token_score(query_token: &str, field_tokens: &[String], fuzzy: bool) -> u32 {
+    let tolerance = if fuzzy {
+        typo_tolerance(query_token)
+    } else {
+        0
+    };
+    let mut best = 0;
+
+    for field_token in field_tokens {
+        let score = if field_token == query_token {
+            EXACT_SCORE
+        } else if field_token.starts_with(query_token) {
+            PREFIX_SCORE
+        } else if query_token.len() >= 3 && field_token.contains(query_token) {
+            SUBSTRING_SCORE
+        } else if tolerance > 0 {
+            // Also compare against the prefix of the same length so a typo
+            // in a partially typed word still matches.
+            let prefix: String = field_token
+                .chars()
+                .take(query_token.chars().count())
+                .collect();
+            bounded_edit_distance(query_token, field_token, tolerance)
+                .or_else(|| bounded_edit_distance(query_token, &prefix, tolerance))
+                .map(|distance| FUZZY_SCORE - (distance as u32 - 1).min(FUZZY_SCORE))
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        best = best.max(score);
+        if best == EXACT_SCORE {
+            break;
+        }
+    }
+
+    best
+}
+
+#[derive(Debug)]
+pub struct SearchQuery {
+    folded: String,
+    tokens: Vec<String>,
+    fuzzy: bool,
+}
+
+impl SearchQuery {
+    pub fn new(query: &str) -> Self {
+        let tokens = tokenize(query);
+        Self {
+            folded: tokens.join(" "),
+            tokens,
+            fuzzy: true,
+        }
+    }
+
+    pub fn tokens(&self) -> &[String] {
+        &self.tokens
+    }
+
+    /// Like `new`, but without typo tolerance. Used for excluded terms so
+    /// `-live` does not also drop songs titled "Love".
+    pub fn strict(query: &str) -> Self {
+        Self {
+            fuzzy: false,
+            ..Self::new(query)
+        }
+    }
+
+    /// Relevance of `song` for this query, or `None` when some query token
+    /// matches none of title, artist and album.
+    pub fn score(&self, song: &Song) -> Option<u32> {
+        if self.tokens.is_empty() {
+            return Some(0);
+        }
+
+        let title = tokenize(&song.title);
+        let artist = tokenize(&song.subtitle);
+        let album = tokenize(&song.album);
+        let mut total = 0;
+
+        for token in &self.tokens {
+            let score = (token_score(token, &title, self.fuzzy) * TITLE_WEIGHT)
+                .max(token_score(token, &artist, self.fuzzy) * ARTIST_WEIGHT)
+                .max(token_score(token, &album, self.fuzzy) * ALBUM_WEIGHT);
+            if score == 0 {
+                return None;
+            }
+            total += score;
+        }
+
+        if self.tokens.len() > 1 {
+            if title.join(" ").contains(&self.folded) {
+                total += PHRASE_BONUS * TITLE_WEIGHT;
+            } else if artist.join(" ").contains(&self.folded) {
+                total += PHRASE_BONUS * ARTIST_WEIGHT;
+            } else if album.join(" ").contains(&self.folded) {
+                total += PHRASE_BONUS * ALBUM_WEIGHT;
+            }
+        }
+
+        Some(total)
+    }
+}
+
+pub fn compare_library_order(a: &Song, b: &Song) -> Ordering {
+    a.subtitle
+        .cmp(&b.subtitle)
+        .then_with(|| a.album.cmp(&b.album))
+        .then_with(|| a.title.cmp(&b.title))
+}
+
+/// Ranks `songs` by relevance and keeps the best `limit` of them. Only the
+/// kept songs are sorted and cloned.
+pub fn rank_songs(mut scored: Vec<(u32, &Song)>, limit: Option<usize>) -> Vec<(u32, &Song)> {
+    let compare = |a: &(u32, &Song), b: &(u32, &Song)| {
+        b.0.cmp(&a.0)
+            .then_with(|| compare_library_order(a.1, b.1))
+            // A total order keeps pages stable across calls.
+            .then_with(|| a.1.path.cmp(&b.1.path))
+    };
+
+    if let Some(limit) = limit {
+        if limit == 0 {
+            return Vec::new();
+        }
+        if scored.len() > limit {
+            scored.select_nth_unstable_by(limit - 1, compare);
+            scored.truncate(limit);
+        }
+    }
+
+    scored.sort_by(compare);
+    scored
+}
+
+/// Index terms a query token should also accept: words containing it and
+/// words within its typo tolerance, mirroring what `token_score` accepts.
+fn similar_terms(token: &str, vocabulary: &[String]) -> Vec<String> {
+    let tolerance = typo_tolerance(token);
+    let length = token.chars().count();
+
+    vocabulary
+        .iter()
+        .filter(|term| {
+            let term_length = term.chars().count();
+            if term_length + tolerance < length || term.as_str() == token {
+                return false;
+            }
+            if length >= 3 && term.contains(token) {
+                return true;
+            }
+            if tolerance == 0 {
+                return false;
+            }
+            if term_length.abs_diff(length) <= tolerance
+                && bounded_edit_distance(token, term, tolerance).is_some()
+            {
+                return true;
+            }
+            let prefix: String = term.chars().take(length).collect();
+            term_length > length && bounded_edit_distance(token, &prefix, tolerance).is_some()
+        })
+        .take(MAX_TERM_EXPANSIONS)
+        .cloned()
+        .collect()
+}
+
+/// Candidates for `query` from the index, in no particular order. Returns
+/// `None` when the index cannot narrow the query down.
+fn search_index<'a>(
+    library: &'a HashMap<String, Song>,
+    index: &SearchIndex,
+    query: &Query,
+    expand: &dyn Fn(&str) -> Vec<String>,
+) -> Option<Vec<&'a Song>> {
+    let text = query.text_match(expand)?;
+    let paths = match index.search(&text.expression, None) {
+        Ok(paths) => paths,
+        Err(error) => {
+            eprintln!("Search index query failed: {}", error);
+            return None;
+        }
+    };
+    Some(
+        paths
+            .iter()
+            .filter_map(|path| library.get(path))
+            .filter(|song| text.matches(song))
+            .collect(),
+    )
+}
+
+/// Songs matching `query`, best first, at most `wanted` of them. Free text
+/// is looked up in the FTS index by prefix together with substring and typo
+/// corrections from the index vocabulary, so a single prefix hit never hides
+/// better matches; the candidates are then ranked like any other match.
+/// Queries the index cannot express fall back to scoring every song.
+pub fn matching_songs<'a>(
+    library: &'a HashMap<String, Song>,
+    index: &SearchIndex,
+    query: &Query,
+    wanted: Option<usize>,
+) -> Vec<&'a Song> {
+    let candidates = if query.text_match(&|_| Vec::new()).is_some() {
+        let vocabulary = index.terms().unwrap_or_else(|error| {
+            eprintln!("Failed to read search index terms: {}", error);
+            Vec::new().into()
+        });
+        let expand = |token: &str| similar_terms(token, &vocabulary);
+        search_index(library, index, query, &expand)
+    } else {
+        None
+    };
+    let candidates: Vec<&Song> = match candidates {
+        Some(candidates) => candidates,
+        None => library.values().collect(),
+    };
+
+    let scored: Vec<(u32, &Song)> = candidates
+        .into_iter()
+        .filter_map(|song| query.score(song).map(|score| (score, song)))
+        .collect();
+
+    rank_songs(scored, wanted)
+        .into_iter()
+        .map(|(_, song)| song)
+        .collect()
+}
+
+/// Runs `query` against the library, skipping `offset` results.
+pub fn search_songs(
+    library: &HashMap<String, Song>,
+    index: &SearchIndex,
+    query: &Query,
+    offset: usize,
+    limit: Option<usize>,
+) -> Vec<Song> {
+    let wanted = limit.map(|limit| offset.saturating_add(limit));
+    matching_songs(library, index, query, wanted)
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, artist: &str) -> Song {
+        Song {
+            title: title.to_string(),
+            subtitle: artist.to_string(),
+            artists: vec![artist.to_string()],
+            album: "Album".to_string(),
+            path: format!("/music/{artist}/{title}.flac"),
+            ..Song::default()
+        }
+    }
+
+    fn library(songs: Vec<Song>) -> (HashMap<String, Song>, SearchIndex) {
+        let index = SearchIndex::new();
+        index.update(songs.iter(), []);
+        let library = songs
+            .into_iter()
+            .map(|song| (song.path.clone(), song))
+            .collect();
+        (library, index)
+    }
+
+    fn titles(library: &HashMap<String, Song>, index: &SearchIndex, text: &str) -> Vec<String> {
+        let query = Query::parse(text).unwrap();
+        matching_songs(library, index, &query, None)
+            .into_iter()
+            .map(|song| song.title.clone())
+            .collect()
+    }
+
+    #[test]
+    fn finds_prefixes() {
+        let (library, index) = library(vec![
+            song("Let It Be", "The Beatles"),
+            song("Crazy in Love", "Beyoncé"),
+            song("Paranoid", "Black Sabbath"),
+        ]);
+
+        assert_eq!(titles(&library, &index, "beat"), ["Let It Be"]);
+    }
+
+    #[test]
+    fn typo_matches_are_found_next_to_prefix_hits() {
+        let (library, index) = library(vec![
+            song("Crazy in Love", "Beyoncé"),
+            song("Beyonse", "Tribute Band"),
+        ]);
+
+        assert_eq!(
+            titles(&library, &index, "beyonse"),
+            ["Beyonse", "Crazy in Love"]
+        );
+        assert_eq!(
+            titles(&library, &index, "beyonce"),
+            ["Crazy in Love", "Beyonse"]
+        );
+    }
+
+    #[test]
+    fn substring_matches_rank_below_whole_words() {
+        let (library, index) = library(vec![
+            song("Glovebox", "Rebecca Black"),
+            song("Love Song", "The Cure"),
+        ]);
+
+        assert_eq!(titles(&library, &index, "love"), ["Love Song", "Glovebox"]);
+    }
+
+    #[test]
+    fn pages_do_not_overlap() {
+        let songs: Vec<Song> = (0..25)
+            .map(|number| song(&format!("Song {number}"), "Band"))
+            .collect();
+        let (library, index) = library(songs);
+        let query = Query::parse("song").unwrap();
+
+        let all = search_songs(&library, &index, &query, 0, None);
+        let mut paged = Vec::new();
+        for offset in (0..all.len()).step_by(10) {
+            paged.extend(search_songs(&library, &index, &query, offset, Some(10)));
+        }
+
+        assert_eq!(all.len(), 25);
+        let paths = |songs: &[Song]| {
+            songs
+                .iter()
+                .map(|song| song.path.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&paged), paths(&all));
+    }
+}
@@ -0,0 +1,742 @@
+use crate::config::config::get_config_path;
+use chrono::Local;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::{Picture, PictureType};
+use lofty::read_from_path;
+use lofty::tag::{Accessor, ItemKey, Tag};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const MAX_TAG_BACKUPS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagField {
+    Title,
+    Artist,
+    AlbumArtist,
+    Album,
+    TrackNumber,
+    TrackTotal,
+    DiscNumber,
+    DiscTotal,
+    Year,
+    Genre,
+    Cover,
+}
+
+/// Requested changes. Missing fields are left alone; an empty string or a
+/// zero clears the field.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TagEdit {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    /// Image file to embed as the front cover; an empty string removes all
+    /// embedded artwork.
+    pub cover_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagFieldChange {
+    pub field: TagField,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagEditPreview {
+    pub path: String,
+    pub changes: Vec<TagFieldChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagEditFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TagWriteReport {
+    pub batch_id: Option<String>,
+    pub written: Vec<String>,
+    pub failed: Vec<TagEditFailure>,
+}
+
+/// Undo data for one file. `save_to_path` may rewrite more than the edited
+/// fields, such as the ID3v2 version or text encoding, so the original bytes
+/// are kept. The audio itself is never rewritten, only moved when a tag in
+/// front of it changes size, so the original is stored as `segments` that
+/// are either still in the edited file or saved in the backup `file`; the
+/// saved ones are usually just the old tag block. While the edit runs, the
+/// backup is a full copy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TagBackupEntry {
+    path: String,
+    file: String,
+    segments: Vec<BackupSegment>,
+    /// Size and modification time right after the edit, or `None` when it
+    /// never finished. Undo leaves files alone that changed since, as
+    /// restoring would discard those changes.
+    edited: Option<(u64, Option<SystemTime>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BackupSegment {
+    /// Bytes the edited file has at `offset`.
+    Shared { offset: u64, length: u64 },
+    /// The next `length` bytes of the backup file.
+    Saved { length: u64 },
+}
+
+/// The manifest is saved before every file is touched, so a crash midway
+/// still leaves the already edited files undoable.
+struct TagBackup {
+    dir: PathBuf,
+    entries: Vec<TagBackupEntry>,
+}
+
+impl TagBackup {
+    fn save(&self) -> Result<(), String> {
+        let manifest =
+            serde_json::to_string_pretty(&self.entries).map_err(|error| error.to_string())?;
+        let staged = self.dir.join("backup.json.tmp");
+        fs::write(&staged, manifest).map_err(|error| error.to_string())?;
+        fs::rename(&staged, self.dir.join("backup.json")).map_err(|error| error.to_string())
+    }
+}
+
+enum CoverChange {
+    Replace(Picture),
+    Remove,
+}
+
+fn backups_dir() -> PathBuf {
+    get_config_path().join("tag-backups")
+}
+
+/// Deletes all but the newest `keep` backups. Batch ids sort by time.
+fn prune_backups(keep: usize) {
+    let Ok(entries) = fs::read_dir(backups_dir()) else {
+        return;
+    };
+    let mut batches: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    batches.sort();
+    let excess = batches.len().saturating_sub(keep);
+    for batch in &batches[..excess] {
+        if let Err(error) = fs::remove_dir_all(batch) {
+            eprintln!("Failed to remove tag backup {:?}: {}", batch, error);
+        }
+    }
+}
+
+impl TagEdit {
+    fn requested(&self) -> Vec<(TagField, Option<String>)> {
+        let text = |value: &Option<String>| {
+            value.as_ref().map(|value| {
+                let value = value.trim();
+                (!value.is_empty()).then(|| value.to_string())
+            })
+        };
+        let number = |value: Option<u32>| value.map(|value| (value > 0).then(|| value.to_string()));
+
+        [
+            (TagField::Title, text(&self.title)),
+            (TagField::Artist, text(&self.artist)),
+            (TagField::AlbumArtist, text(&self.album_artist)),
+            (TagField::Album, text(&self.album)),
+            (TagField::TrackNumber, number(self.track_number)),
+            (TagField::TrackTotal, number(self.track_total)),
+            (TagField::DiscNumber, number(self.disc_number)),
+            (TagField::DiscTotal, number(self.disc_total)),
+            (TagField::Year, number(self.year)),
+            (TagField::Genre, text(&self.genre)),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| value.map(|value| (field, value)))
+        .collect()
+    }
+
+    fn cover_change(&self) -> Result<Option<CoverChange>, String> {
+        let Some(path) = self.cover_path.as_deref().map(str::trim) else {
+            return Ok(None);
+        };
+        if path.is_empty() {
+            return Ok(Some(CoverChange::Remove));
+        }
+
+        let mut file = File::open(path).map_err(|error| error.to_string())?;
+        let picture = Picture::from_reader(&mut file).map_err(|error| error.to_string())?;
+        Ok(Some(CoverChange::Replace(Picture::new_unchecked(
+            PictureType::CoverFront,
+            picture.mime_type().cloned(),
+            None,
+            picture.into_data(),
+        ))))
+    }
+}
+
+fn read_field(tag: &Tag, field: TagField) -> Option<String> {
+    match field {
+        TagField::Title => tag.title().map(|value| value.to_string()),
+        TagField::Artist => tag.artist().map(|value| value.to_string()),
+        TagField::AlbumArtist => tag.get_string(&ItemKey::AlbumArtist).map(str::to_string),
+        TagField::Album => tag.album().map(|value| value.to_string()),
+        TagField::TrackNumber => tag.track().map(|value| value.to_string()),
+        TagField::TrackTotal => tag.track_total().map(|value| value.to_string()),
+        TagField::DiscNumber => tag.disk().map(|value| value.to_string()),
+        TagField::DiscTotal => tag.disk_total().map(|value| value.to_string()),
+        TagField::Year => tag.year().map(|value| value.to_string()),
+        TagField::Genre => tag.genre().map(|value| value.to_string()),
+        TagField::Cover => tag
+            .pictures()
+            .first()
+            .map(|picture| match picture.mime_type() {
+                Some(mime_type) => mime_type.to_string(),
+                None => "image".to_string(),
+            }),
+    }
+}
+
+fn write_field(tag: &mut Tag, field: TagField, value: Option<&str>) -> Result<(), String> {
+    let number = |value: &str| {
+        value
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid number for {:?}: {}", field, value))
+    };
+
+    match (field, value) {
+        (TagField::Title, Some(value)) => tag.set_title(value.to_string()),
+        (TagField::Title, None) => tag.remove_title(),
+        (TagField::Artist, Some(value)) => tag.set_artist(value.to_string()),
+        (TagField::Artist, None) => tag.remove_artist(),
+        (TagField::AlbumArtist, Some(value)) => {
+            tag.insert_text(ItemKey::AlbumArtist, value.to_string());
+        }
+        (TagField::AlbumArtist, None) => tag.remove_key(&ItemKey::AlbumArtist),
+        (TagField::Album, Some(value)) => tag.set_album(value.to_string()),
+        (TagField::Album, None) => tag.remove_album(),
+        (TagField::TrackNumber, Some(value)) => tag.set_track(number(value)?),
+        (TagField::TrackNumber, None) => tag.remove_track(),
+        (TagField::TrackTotal, Some(value)) => tag.set_track_total(number(value)?),
+        (TagField::TrackTotal, None) => tag.remove_track_total(),
+        (TagField::DiscNumber, Some(value)) => tag.set_disk(number(value)?),
+        (TagField::DiscNumber, None) => tag.remove_disk(),
+        (TagField::DiscTotal, Some(value)) => tag.set_disk_total(number(value)?),
+        (TagField::DiscTotal, None) => tag.remove_disk_total(),
+        (TagField::Year, Some(value)) => tag.set_year(number(value)?),
+        (TagField::Year, None) => tag.remove_year(),
+        (TagField::Genre, Some(value)) => tag.set_genre(value.to_string()),
+        (TagField::Genre, None) => tag.remove_genre(),
+        (TagField::Cover, _) => {}
+    }
+    Ok(())
+}
+
+fn clear_pictures(tag: &mut Tag) -> Vec<Picture> {
+    let mut pictures = Vec::with_capacity(tag.pictures().len());
+    while !tag.pictures().is_empty() {
+        pictures.push(tag.remove_picture(0));
+    }
+    pictures
+}
+
+fn apply_cover(tag: &mut Tag, change: &CoverChange) {
+    let previous = clear_pictures(tag);
+    if let CoverChange::Replace(cover) = change {
+        // Rift shows the first picture, so the new cover goes in front of
+        // any other artwork the file carries.
+        tag.push_picture(cover.clone());
+        for picture in previous {
+            if picture.pic_type() != PictureType::CoverFront {
+                tag.push_picture(picture);
+            }
+        }
+    }
+}
+
+fn open_for_edit(path: &str) -> Result<lofty::file::TaggedFile, String> {
+    let mut tagged_file = read_from_path(path).map_err(|error| error.to_string())?;
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    Ok(tagged_file)
+}
+
+fn file_state(path: &str) -> Result<(u64, Option<SystemTime>), String> {
+    let metadata = fs::metadata(path).map_err(|error| error.to_string())?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+fn edit_file(
+    path: &str,
+    requested: &[(TagField, Option<String>)],
+    cover: Option<&CoverChange>,
+    backup: &mut TagBackup,
+    entry_index: usize,
+) -> Result<(), String> {
+    let mut tagged_file = open_for_edit(path)?;
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or_else(|| "File cannot hold tags".to_string())?;
+
+    for (field, value) in requested {
+        write_field(tag, *field, value.as_deref())?;
+    }
+    if let Some(cover) = cover {
+        apply_cover(tag, cover);
+    }
+
+    let copy = format!("{}.orig", entry_index);
+    let length = fs::copy(path, backup.dir.join(&copy)).map_err(|error| error.to_string())?;
+    backup.entries.push(TagBackupEntry {
+        path: path.to_string(),
+        file: copy.clone(),
+        segments: vec![BackupSegment::Saved { length }],
+        edited: None,
+    });
+    let saved = backup.save();
+    let written = saved.and_then(|()| {
+        tagged_file
+            .save_to_path(path, WriteOptions::default())
+            .map_err(|error| error.to_string())
+    });
+    if let Err(error) = written {
+        // The write may have stopped halfway; put the original back.
+        let entry = backup.entries.last().expect("entry was just added");
+        if let Err(restore_error) = restore_file(entry, &backup.dir) {
+            eprintln!(
+                "Failed to restore {} after a failed edit: {}",
+                path, restore_error
+            );
+            return Err(error);
+        }
+        backup.entries.pop();
+        let _ = fs::remove_file(backup.dir.join(&copy));
+        backup.save()?;
+        return Err(error);
+    }
+
+    let entry = backup.entries.last_mut().expect("entry was just added");
+    entry.edited = Some(file_state(path)?);
+    backup.save()?;
+
+    // Shrinking the full copy is only an optimisation; it stays usable.
+    if let Err(error) = shrink_backup(backup, entry_index) {
+        eprintln!("Failed to shrink tag backup of {}: {}", path, error);
+    }
+    Ok(())
+}
+
+/// Replaces the full copy of the last entry with the bytes the edit changed.
+fn shrink_backup(backup: &mut TagBackup, entry_index: usize) -> Result<(), String> {
+    let entry = backup.entries.last().expect("shrinking an existing entry");
+    let copy = backup.dir.join(&entry.file);
+    let segments =
+        changed_segments(&copy, Path::new(&entry.path)).map_err(|error| error.to_string())?;
+
+    let file = format!("{}.bin", entry_index);
+    let mut original = File::open(&copy).map_err(|error| error.to_string())?;
+    let mut output = File::create(backup.dir.join(&file)).map_err(|error| error.to_string())?;
+    let mut position = 0;
+    for segment in &segments {
+        match segment {
+            BackupSegment::Shared { length, .. } => position += length,
+            BackupSegment::Saved { length } => {
+                copy_range(&mut original, position, *length, &mut output)
+                    .map_err(|error| error.to_string())?;
+                position += length;
+            }
+        }
+    }
+    output.sync_all().map_err(|error| error.to_string())?;
+
+    let entry = backup
+        .entries
+        .last_mut()
+        .expect("shrinking an existing entry");
+    entry.file = file;
+    entry.segments = segments;
+    backup.save()?;
+    fs::remove_file(copy).map_err(|error| error.to_string())
+}
+
+const COMPARE_CHUNK: usize = 64 * 1024;
+/// Shorter matches, e.g. inside a rewritten tag, are not worth a segment.
+const MIN_SHARED_LENGTH: u64 = 4096;
+
+/// Describes `original` in terms of `edited`. Tags live at the start or the
+/// end of a file, so the audio is either where it was or shifted by the
+/// change in size; whichever alignment shares more bytes wins.
+fn changed_segments(original: &Path, edited: &Path) -> io::Result<Vec<BackupSegment>> {
+    let growth = fs::metadata(edited)?.len() as i64 - fs::metadata(original)?.len() as i64;
+    let in_place = segments_at(original, edited, 0)?;
+    if growth == 0 {
+        return Ok(in_place);
+    }
+    let shifted = segments_at(original, edited, growth)?;
+    Ok(if shared_length(&shifted) > shared_length(&in_place) {
+        shifted
+    } else {
+        in_place
+    })
+}
+
+fn shared_length(segments: &[BackupSegment]) -> u64 {
+    segments
+        .iter()
+        .map(|segment| match segment {
+            BackupSegment::Shared { length, .. } => *length,
+            BackupSegment::Saved { .. } => 0,
+        })
+        .sum()
+}
+
+/// Compares byte `i` of `original` with byte `i + shift` of `edited`.
+fn segments_at(original: &Path, edited: &Path, shift: i64) -> io::Result<Vec<BackupSegment>> {
+    let mut original = File::open(original)?;
+    let mut edited = File::open(edited)?;
+    let original_length = original.metadata()?.len();
+    let edited_length = edited.metadata()?.len() as i64;
+
+    let overlap_start = ((-shift).max(0) as u64).min(original_length);
+    let overlap_end = ((edited_length - shift).max(0) as u64)
+        .min(original_length)
+        .max(overlap_start);
+
+    // Runs of equal and differing bytes, in order.
+    let mut runs: Vec<(bool, u64)> = Vec::new();
+    let mut push_run = |shared: bool, length: u64| match runs.last_mut() {
+        _ if length == 0 => {}
+        Some((last, total)) if *last == shared => *total += length,
+        _ => runs.push((shared, length)),
+    };
+
+    push_run(false, overlap_start);
+    original.seek(SeekFrom::Start(overlap_start))?;
+    edited.seek(SeekFrom::Start((overlap_start as i64 + shift) as u64))?;
+    let mut original = BufReader::new(original);
+    let mut edited = BufReader::new(edited);
+    let mut buffer_a = vec![0; COMPARE_CHUNK];
+    let mut buffer_b = vec![0; COMPARE_CHUNK];
+    let mut position = overlap_start;
+    while position < overlap_end {
+        let length = COMPARE_CHUNK.min((overlap_end - position) as usize);
+        original.read_exact(&mut buffer_a[..length])?;
+        edited.read_exact(&mut buffer_b[..length])?;
+        for (a, b) in buffer_a[..length].iter().zip(&buffer_b[..length]) {
+            push_run(a == b, 1);
+        }
+        position += length as u64;
+    }
+    push_run(false, original_length - overlap_end);
+
+    let mut segments = Vec::new();
+    let mut position = 0;
+    for (shared, length) in runs {
+        if shared && length >= MIN_SHARED_LENGTH {
+            segments.push(BackupSegment::Shared {
+                offset: (position as i64 + shift) as u64,
+                length,
+            });
+        } else if let Some(BackupSegment::Saved { length: saved }) = segments.last_mut() {
+            *saved += length;
+        } else {
+            segments.push(BackupSegment::Saved { length });
+        }
+        position += length;
+    }
+    Ok(segments)
+}
+
+fn copy_range(from: &mut File, start: u64, length: u64, to: &mut impl Write) -> io::Result<()> {
+    from.seek(SeekFrom::Start(start))?;
+    let copied = io::copy(&mut from.take(length), to)?;
+    if copied < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Backup is shorter than expected",
+        ));
+    }
+    Ok(())
+}
+
+pub fn preview_tag_edit(paths: &[String], edit: &TagEdit) -> Result<Vec<TagEditPreview>, String> {
+    let requested = edit.requested();
+    let cover_after = match edit.cover_path.as_deref().map(str::trim) {
+        Some("") => Some(None),
+        Some(path) => Some(Some(
+            Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.to_string()),
+        )),
+        None => None,
+    };
+
+    let mut previews = Vec::new();
+    for path in paths {
+        let tagged_file = read_from_path(path).map_err(|error| format!("{}: {}", path, error))?;
+        let empty = Tag::new(tagged_file.primary_tag_type());
+        let tag = tagged_file.primary_tag().unwrap_or(&empty);
+
+        let mut changes: Vec<TagFieldChange> = requested
+            .iter()
+            .map(|(field, after)| TagFieldChange {
+                field: *field,
+                before: read_field(tag, *field),
+                after: after.clone(),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+        if let Some(after) = &cover_after {
+            changes.push(TagFieldChange {
+                field: TagField::Cover,
+                before: read_field(tag, TagField::Cover),
+                after: after.clone(),
+            });
+        }
+
+        previews.push(TagEditPreview {
+            path: path.clone(),
+            changes,
+        });
+    }
+    Ok(previews)
+}
+
+/// Writes `edit` to every file in `paths`, keeping the bytes it replaced in
+/// a backup that `undo_tag_edit` can restore. Only the newest
+/// `MAX_TAG_BACKUPS` backups are kept.
+pub fn apply_tag_edit(paths: &[String], edit: &TagEdit) -> Result<TagWriteReport, String> {
+    let requested = edit.requested();
+    let cover = edit.cover_change()?;
+    if requested.is_empty() && cover.is_none() {
+        return Err("No tag changes requested".to_string());
+    }
+
+    prune_backups(MAX_TAG_BACKUPS - 1);
+    let batch_id = Local::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+    let mut backup = TagBackup {
+        dir: backups_dir().join(&batch_id),
+        entries: Vec::new(),
+    };
+    fs::create_dir_all(&backup.dir).map_err(|error| error.to_string())?;
+
+    let mut report = TagWriteReport::default();
+    for (index, path) in paths.iter().enumerate() {
+        match edit_file(path, &requested, cover.as_ref(), &mut backup, index) {
+            Ok(()) => report.written.push(path.clone()),
+            Err(error) => {
+                eprintln!("Failed to write tags to {}: {}", path, error);
+                report.failed.push(TagEditFailure {
+                    path: path.clone(),
+                    error,
+                });
+            }
+        }
+    }
+
+    if backup.entries.is_empty() {
+        let _ = fs::remove_dir_all(&backup.dir);
+        return Ok(report);
+    }
+    report.batch_id = Some(batch_id);
+    Ok(report)
+}
+
+fn restore_file(entry: &TagBackupEntry, batch_dir: &Path) -> Result<(), String> {
+    if let Some(edited) = entry.edited {
+        if file_state(&entry.path)? != edited {
+            return Err("File changed since the edit".to_string());
+        }
+    }
+
+    // Assembled next to the original first, then copied over it in place
+    // rather than renamed, so the file keeps the identity its track id is
+    // derived from. If that copy fails, the staged original is kept.
+    let path = Path::new(&entry.path);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Invalid path: {}", entry.path))?;
+    let staged = path.with_file_name(format!(".{}.undo", file_name.to_string_lossy()));
+    if let Err(error) = assemble_original(entry, batch_dir, &staged) {
+        let _ = fs::remove_file(&staged);
+        return Err(error.to_string());
+    }
+    overwrite_with(path, &staged).map_err(|error| {
+        format!(
+            "{}; the original was kept at {}",
+            error,
+            staged.to_string_lossy()
+        )
+    })?;
+    let _ = fs::remove_file(&staged);
+    Ok(())
+}
+
+fn overwrite_with(path: &Path, source: &Path) -> io::Result<()> {
+    let mut source = File::open(source)?;
+    let mut output = OpenOptions::new().write(true).truncate(true).open(path)?;
+    io::copy(&mut source, &mut output)?;
+    output.sync_all()
+}
+
+fn assemble_original(entry: &TagBackupEntry, batch_dir: &Path, output: &Path) -> io::Result<()> {
+    let mut edited = File::open(&entry.path)?;
+    let mut saved = File::open(batch_dir.join(&entry.file))?;
+    let mut output = File::create(output)?;
+
+    for segment in &entry.segments {
+        match segment {
+            BackupSegment::Shared { offset, length } => {
+                copy_range(&mut edited, *offset, *length, &mut output)?
+            }
+            BackupSegment::Saved { length } => {
+                let position = saved.stream_position()?;
+                copy_range(&mut saved, position, *length, &mut output)?
+            }
+        }
+    }
+    output.sync_all()
+}
+
+/// Puts back the files an earlier `apply_tag_edit` changed. The backup is
+/// deleted once every file has been restored.
+pub fn undo_tag_edit(batch_id: &str) -> Result<TagWriteReport, String> {
+    if batch_id.is_empty()
+        || !batch_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(format!("Invalid tag backup id: {}", batch_id));
+    }
+
+    let batch_dir = backups_dir().join(batch_id);
+    let manifest = fs::read_to_string(batch_dir.join("backup.json"))
+        .map_err(|_| format!("Tag backup not found: {}", batch_id))?;
+    let entries: Vec<TagBackupEntry> =
+        serde_json::from_str(&manifest).map_err(|error| error.to_string())?;
+
+    let mut report = TagWriteReport::default();
+    for entry in &entries {
+        match restore_file(entry, &batch_dir) {
+            Ok(()) => report.written.push(entry.path.clone()),
+            Err(error) => {
+                eprintln!("Failed to restore tags of {}: {}", entry.path, error);
+                report.failed.push(TagEditFailure {
+                    path: entry.path.clone(),
+                    error,
+                });
+            }
+        }
+    }
+
+    if report.failed.is_empty() {
+        if let Err(error) = fs::remove_dir_all(&batch_dir) {
+            eprintln!("Failed to remove tag backup {}: {}", batch_id, error);
+        }
+    } else {
+        report.batch_id = Some(batch_id.to_string());
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index * 7 % 251) as u8).collect()
+    }
+
+    /// Backs `original` up against `edited` and restores it again.
+    fn round_trip(name: &str, original: &[u8], edited: &[u8]) -> Vec<BackupSegment> {
+        let dir = std::env::temp_dir().join(format!("rift-tags-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let (original_path, edited_path) = (dir.join("original"), dir.join("edited"));
+        fs::write(&original_path, original).unwrap();
+        fs::write(&edited_path, edited).unwrap();
+
+        let mut backup = TagBackup {
+            dir: dir.clone(),
+            entries: vec![TagBackupEntry {
+                path: edited_path.to_string_lossy().to_string(),
+                file: "original".to_string(),
+                segments: vec![BackupSegment::Saved {
+                    length: original.len() as u64,
+                }],
+                edited: None,
+            }],
+        };
+        shrink_backup(&mut backup, 0).unwrap();
+        assert!(!original_path.exists());
+
+        let entry = &backup.entries[0];
+        restore_file(entry, &dir).unwrap();
+        assert_eq!(fs::read(&edited_path).unwrap(), original);
+        let segments = entry.segments.clone();
+        fs::remove_dir_all(&dir).unwrap();
+        segments
+    }
+
+    #[test]
+    fn tag_in_front_that_grew() {
+        let samples = audio(50_000);
+        let original = [b"ID3 old".as_slice(), &samples].concat();
+        let edited = [b"ID3 a longer new tag".as_slice(), &samples].concat();
+
+        let segments = round_trip("front", &original, &edited);
+        assert_eq!(
+            segments,
+            [
+                BackupSegment::Saved { length: 7 },
+                BackupSegment::Shared {
+                    offset: 20,
+                    length: 50_000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn size_header_and_trailing_tag() {
+        let samples = audio(50_000);
+        let original = [b"RIFF\x01\x00".as_slice(), &samples, b"old trailing tag"].concat();
+        let edited = [b"RIFF\x02\x00".as_slice(), &samples, b"new"].concat();
+
+        let segments = round_trip("trailing", &original, &edited);
+        let saved: u64 = segments
+            .iter()
+            .map(|segment| match segment {
+                BackupSegment::Saved { length } => *length,
+                BackupSegment::Shared { .. } => 0,
+            })
+            .sum();
+        assert!(saved < 100, "{:?}", segments);
+    }
+
+    #[test]
+    fn unrelated_files_are_saved_whole() {
+        let segments = round_trip("unrelated", &audio(10_000), b"short");
+        assert_eq!(segments, [BackupSegment::Saved { length: 10_000 }]);
+    }
+}
@@ -0,0 +1,223 @@
+use super::history::ListeningHistoryStore;
+use super::library::{LibraryChange, MusicLibrary};
+use super::lyrics::is_lyrics_file;
+use super::metadata::is_folder_image;
+use super::scanner::{is_audio_file, library_roots};
+use crate::commands::commands::queue_library_analysis;
+use crate::config::config::load_config;
+use crate::playlists::store::PlaylistStore;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+pub const LIBRARY_CHANGED_EVENT: &str = "library-changed";
+
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(750);
+const MAX_DEBOUNCE_WAIT: Duration = Duration::from_secs(5);
+
+pub struct LibraryWatcher {
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    roots: Mutex<Vec<PathBuf>>,
+}
+
+impl LibraryWatcher {
+    pub fn start(app: AppHandle) -> Self {
+        let (tx, rx) = mpsc::channel::<Event>();
+
+        let handler = move |result: notify::Result<Event>| match result {
+            Ok(event) => {
+                let _ = tx.send(event);
+            }
+            Err(error) => eprintln!("Library watcher error: {}", error),
+        };
+        let watcher = match notify::recommended_watcher(handler) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                eprintln!("Cannot start library watcher: {}", error);
+                None
+            }
+        };
+
+        thread::spawn(move || {
+            run_watch_loop(app, rx);
+        });
+
+        let library_watcher = Self {
+            watcher: Mutex::new(watcher),
+            roots: Mutex::new(Vec::new()),
+        };
+        library_watcher.set_roots(library_roots(&load_config()));
+        library_watcher
+    }
+
+    pub fn set_roots(&self, roots: Vec<PathBuf>) {
+        let mut watcher = self.watcher.lock().unwrap();
+        let Some(watcher) = watcher.as_mut() else {
+            return;
+        };
+        let mut current = self.roots.lock().unwrap();
+
+        for root in current.iter() {
+            if !roots.contains(root) {
+                let _ = watcher.unwatch(root);
+            }
+        }
+
+        for root in &roots {
+            if current.contains(root) || !root.exists() {
+                continue;
+            }
+            if let Err(error) = watcher.watch(root, RecursiveMode::Recursive) {
+                eprintln!("Cannot watch {:?}: {}", root, error);
+            }
+        }
+
+        *current = roots;
+    }
+}
+
+fn run_watch_loop(app: AppHandle, rx: mpsc::Receiver<Event>) {
+    while let Ok(first) = rx.recv() {
+        let mut events = vec![first];
+        let started = Instant::now();
+
+        // Wait for the burst to settle so a copy of a whole album is applied
+        // as one update instead of one per written block.
+        while started.elapsed() < MAX_DEBOUNCE_WAIT {
+            match rx.recv_timeout(DEBOUNCE_INTERVAL) {
+                Ok(event) => events.push(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let changes = coalesce_events(events);
+        if !changes.is_empty() {
+            apply_library_changes(&app, &changes);
+        }
+    }
+}
+
+fn coalesce_events(events: Vec<Event>) -> Vec<LibraryChange> {
+    let mut changes = Vec::new();
+    let mut moved = HashSet::new();
+    let mut touched = Vec::new();
+    let mut seen = HashSet::new();
+
+    for event in events {
+        match event.kind {
+            EventKind::Access(_) => continue,
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                if event.paths.len() == 2
+                    && !event.paths.iter().any(|path| is_lyrics_file(path)) =>
+            {
+                let from = event.paths[0].clone();
+                let to = event.paths[1].clone();
+                moved.insert(from.clone());
+                moved.insert(to.clone());
+                changes.push(LibraryChange::Moved { from, to });
+            }
+            _ => {
+                for path in event.paths {
+                    if seen.insert(path.clone()) {
+                        touched.push(path);
+                    }
+                }
+            }
+        }
+    }
+
+    // The last state on disk wins, so create/modify/delete bursts on the
+    // same path collapse into a single change.
+    for path in touched {
+        if moved.contains(&path) {
+            continue;
+        }
+        // A sidecar appearing, changing or going away only affects the
+        // tracks it belongs to.
+        if is_lyrics_file(&path) {
+            for audio in audio_files_for_sidecar(&path) {
+                if seen.insert(audio.clone()) {
+                    changes.push(LibraryChange::Changed(audio));
+                }
+            }
+            continue;
+        }
+        // Folder artwork is picked up by rescanning the folder it sits in.
+        if is_folder_image(&path) {
+            if let Some(parent) = path.parent() {
+                if seen.insert(parent.to_path_buf()) {
+                    changes.push(LibraryChange::Changed(parent.to_path_buf()));
+                }
+            }
+            continue;
+        }
+        if path.exists() {
+            if path.is_dir() || is_audio_file(&path) {
+                changes.push(LibraryChange::Changed(path));
+            }
+        } else {
+            changes.push(LibraryChange::Removed(path));
+        }
+    }
+
+    changes
+}
+
+/// Audio files a sidecar belongs to, whether or not the sidecar still exists.
+fn audio_files_for_sidecar(lyrics_path: &Path) -> Vec<PathBuf> {
+    let (Some(stem), Some(parent)) = (lyrics_path.file_stem(), lyrics_path.parent()) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_audio_file(path) && path.file_stem() == Some(stem))
+        .collect()
+}
+
+fn apply_library_changes(app: &AppHandle, changes: &[LibraryChange]) {
+    let library = app.state::<MusicLibrary>();
+    let update = library.apply_changes(changes);
+
+    if !update.moved.is_empty() {
+        if let Err(error) = app.state::<PlaylistStore>().move_track_paths(&update.moved) {
+            eprintln!("Failed to move playlist tracks: {error}");
+        }
+        if let Err(error) = app
+            .state::<ListeningHistoryStore>()
+            .move_paths(&update.moved)
+        {
+            eprintln!("Failed to move listening history entries: {error}");
+        }
+    }
+
+    // Track ids do not change when a file is edited, so only a batch that
+    // removed or added paths can leave references to relink. Additions
+    // count too, as the two halves of a move may arrive in separate batches.
+    if update.summary.removed > 0 || update.summary.added > 0 || !update.moved.is_empty() {
+        library.relink_references(
+            &app.state::<PlaylistStore>(),
+            &app.state::<ListeningHistoryStore>(),
+        );
+    }
+
+    if update.has_changes() {
+        if let Err(error) = app.emit(LIBRARY_CHANGED_EVENT, &update) {
+            eprintln!("Failed to emit library change: {error}");
+        }
+        // New and moved files are fingerprinted right away rather than at
+        // the next rescan, so duplicate and move matching sees them.
+        queue_library_analysis(app.clone());
+    }
+}
@@ -1 +1,2 @@
+pub mod smart;
 pub mod store;
@@ -0,0 +1,504 @@
+use crate::models::models::Song;
+use crate::music::browse::{album_artist_of, compare_album_order};
+use crate::music::history::HistoryTrackRecord;
+use crate::music::listing::SortDirection;
+use crate::music::query::format_matches;
+use crate::music::search::fold_text;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleField {
+    Artist,
+    Genre,
+    Format,
+    Year,
+    PlayCount,
+    /// 1 to 5 stars; unrated tracks count as 0.
+    Rating,
+    AddedAt,
+    LastPlayed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleOperator {
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    AtLeast,
+    AtMost,
+    /// The value is a number of days.
+    InLastDays,
+    NotInLastDays,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RuleValue {
+    Number(i64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmartRule {
+    pub field: RuleField,
+    pub operator: RuleOperator,
+    pub value: RuleValue,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMatch {
+    #[default]
+    All,
+    Any,
+}
+
+/// Rules combined with AND (`all`) or OR (`any`). Only the top-level group
+/// may be empty, which matches every track.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleGroup {
+    #[serde(default)]
+    pub matching: RuleMatch,
+    #[serde(default)]
+    pub rules: Vec<SmartCondition>,
+}
+
+/// Tagged, e.g. `{"type": "rule", "field": "artist", ...}`, so a malformed
+/// rule is an error instead of being read as some other condition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SmartCondition {
+    Rule(SmartRule),
+    Group(RuleGroup),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartSortKey {
+    Title,
+    #[default]
+    Artist,
+    Album,
+    Year,
+    AddedAt,
+    PlayCount,
+    LastPlayed,
+    Rating,
+    Random,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SmartPlaylistDefinition {
+    #[serde(flatten)]
+    pub rules: RuleGroup,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub sort: SmartSortKey,
+    #[serde(default)]
+    pub direction: SortDirection,
+}
+
+impl SmartPlaylistDefinition {
+    /// Rejects rules whose operator or value does not fit the field, so a
+    /// saved playlist never silently matches nothing.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.limit == Some(0) {
+            return Err("Limit must be at least 1".to_string());
+        }
+        validate_group(&self.rules)
+    }
+}
+
+fn validate_group(group: &RuleGroup) -> Result<(), String> {
+    for condition in &group.rules {
+        match condition {
+            SmartCondition::Rule(rule) => validate_rule(rule)?,
+            SmartCondition::Group(group) if group.rules.is_empty() => {
+                return Err("Rule groups must contain at least one rule".to_string());
+            }
+            SmartCondition::Group(group) => validate_group(group)?,
+        }
+    }
+    Ok(())
+}
+
+fn validate_rule(rule: &SmartRule) -> Result<(), String> {
+    use RuleOperator::*;
+
+    let (operators, expects_text): (&[RuleOperator], bool) = match rule.field {
+        RuleField::Artist | RuleField::Genre | RuleField::Format => {
+            (&[Is, IsNot, Contains, DoesNotContain], true)
+        }
+        RuleField::Year | RuleField::PlayCount | RuleField::Rating => {
+            (&[Is, IsNot, AtLeast, AtMost], false)
+        }
+        RuleField::AddedAt | RuleField::LastPlayed => (&[InLastDays, NotInLastDays], false),
+    };
+    if !operators.contains(&rule.operator) {
+        return Err(format!(
+            "{:?} cannot be used with {:?}",
+            rule.operator, rule.field
+        ));
+    }
+    match (&rule.value, expects_text) {
+        (RuleValue::Text(text), true) if !text.trim().is_empty() => Ok(()),
+        (RuleValue::Number(number), false) if *number >= 0 => Ok(()),
+        _ => Err(format!("Invalid value for {:?}", rule.field)),
+    }
+}
+
+/// Play count and last play per path, from the listening history.
+struct Plays(HashMap<String, (usize, i64)>);
+
+impl Plays {
+    fn new(history: &[HistoryTrackRecord]) -> Self {
+        Self(
+            history
+                .iter()
+                .map(|record| {
+                    (
+                        record.track_path.clone(),
+                        (record.play_count, record.last_played_at),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn count(&self, song: &Song) -> usize {
+        self.0.get(&song.path).map_or(0, |(count, _)| *count)
+    }
+
+    fn last_played(&self, song: &Song) -> Option<i64> {
+        self.0.get(&song.path).map(|(_, last_played)| *last_played)
+    }
+}
+
+/// Tracks matching the definition, sorted and limited. `now` is a Unix
+/// timestamp in seconds.
+pub fn evaluate(
+    definition: &SmartPlaylistDefinition,
+    library: &HashMap<String, Song>,
+    history: &[HistoryTrackRecord],
+    now: i64,
+) -> Vec<Song> {
+    let plays = Plays::new(history);
+    let mut songs: Vec<&Song> = library
+        .values()
+        .filter(|song| group_matches(&definition.rules, song, &plays, now))
+        .collect();
+
+    if definition.sort == SmartSortKey::Random {
+        songs.shuffle(&mut rand::thread_rng());
+    } else {
+        songs.sort_by(|a, b| {
+            let primary = compare(definition.sort, a, b, &plays);
+            let primary = if definition.direction == SortDirection::Desc {
+                primary.reverse()
+            } else {
+                primary
+            };
+            primary
+                .then_with(|| album_artist_of(a).cmp(album_artist_of(b)))
+                .then_with(|| a.album.cmp(&b.album))
+                .then_with(|| compare_album_order(a, b))
+        });
+    }
+    if let Some(limit) = definition.limit {
+        songs.truncate(limit);
+    }
+    songs.into_iter().cloned().collect()
+}
+
+fn compare(sort: SmartSortKey, a: &Song, b: &Song, plays: &Plays) -> Ordering {
+    match sort {
+        SmartSortKey::Title => fold_text(&a.title).cmp(&fold_text(&b.title)),
+        SmartSortKey::Artist => fold_text(album_artist_of(a)).cmp(&fold_text(album_artist_of(b))),
+        SmartSortKey::Album => fold_text(&a.album).cmp(&fold_text(&b.album)),
+        SmartSortKey::Year => a.year.cmp(&b.year),
+        SmartSortKey::AddedAt => a.added_at.cmp(&b.added_at),
+        SmartSortKey::PlayCount => plays.count(a).cmp(&plays.count(b)),
+        SmartSortKey::LastPlayed => plays.last_played(a).cmp(&plays.last_played(b)),
+        SmartSortKey::Rating => a.rating.cmp(&b.rating),
+        SmartSortKey::Random => Ordering::Equal,
+    }
+}
+
+fn group_matches(group: &RuleGroup, song: &Song, plays: &Plays, now: i64) -> bool {
+    let mut conditions = group.rules.iter().map(|condition| match condition {
+        SmartCondition::Rule(rule) => rule_matches(rule, song, plays, now),
+        SmartCondition::Group(group) => group_matches(group, song, plays, now),
+    });
+    match group.matching {
+        RuleMatch::All => conditions.all(|matches| matches),
+        RuleMatch::Any => group.rules.is_empty() || conditions.any(|matches| matches),
+    }
+}
+
+fn rule_matches(rule: &SmartRule, song: &Song, plays: &Plays, now: i64) -> bool {
+    match (&rule.value, rule.field) {
+        (RuleValue::Text(value), RuleField::Artist) => {
+            let names = song
+                .artists
+                .iter()
+                .map(String::as_str)
+                .chain([song.subtitle.as_str(), album_artist_of(song)]);
+            text_matches(rule.operator, names, value)
+        }
+        (RuleValue::Text(value), RuleField::Genre) => {
+            text_matches(rule.operator, song.genre.as_deref(), value)
+        }
+        (RuleValue::Text(value), RuleField::Format) => {
+            let value = fold_text(value).replace(' ', "");
+            match rule.operator {
+                RuleOperator::Is => format_matches(song, &value),
+                RuleOperator::IsNot => !format_matches(song, &value),
+                operator => text_matches(operator, [song.codec.as_str()], &value),
+            }
+        }
+        (RuleValue::Number(value), RuleField::Year) => {
+            number_matches(rule.operator, song.year.map(i64::from), *value)
+        }
+        (RuleValue::Number(value), RuleField::PlayCount) => {
+            number_matches(rule.operator, Some(plays.count(song) as i64), *value)
+        }
+        (RuleValue::Number(value), RuleField::Rating) => number_matches(
+            rule.operator,
+            Some(song.rating.map_or(0, i64::from)),
+            *value,
+        ),
+        (RuleValue::Number(days), RuleField::AddedAt) => {
+            date_matches(rule.operator, Some(song.added_at), *days, now)
+        }
+        (RuleValue::Number(days), RuleField::LastPlayed) => {
+            date_matches(rule.operator, plays.last_played(song), *days, now)
+        }
+        _ => false,
+    }
+}
+
+fn text_matches<'a>(
+    operator: RuleOperator,
+    texts: impl IntoIterator<Item = &'a str>,
+    value: &str,
+) -> bool {
+    let value = fold_text(value.trim());
+    let mut texts = texts.into_iter().map(fold_text);
+    match operator {
+        RuleOperator::Is => texts.any(|text| text.trim() == value),
+        RuleOperator::IsNot => texts.all(|text| text.trim() != value),
+        RuleOperator::Contains => texts.any(|text| text.contains(&value)),
+        RuleOperator::DoesNotContain => texts.all(|text| !text.contains(&value)),
+        _ => false,
+    }
+}
+
+/// Tracks without a value, such as an untagged year, only match `is_not`.
+fn number_matches(operator: RuleOperator, number: Option<i64>, value: i64) -> bool {
+    match (operator, number) {
+        (RuleOperator::Is, Some(number)) => number == value,
+        (RuleOperator::IsNot, number) => number != Some(value),
+        (RuleOperator::AtLeast, Some(number)) => number >= value,
+        (RuleOperator::AtMost, Some(number)) => number <= value,
+        _ => false,
+    }
+}
+
+/// Never played tracks are not in the last days, so they match
+/// `not_in_last_days`.
+fn date_matches(operator: RuleOperator, timestamp: Option<i64>, days: i64, now: i64) -> bool {
+    let since = now.saturating_sub(days.saturating_mul(SECONDS_PER_DAY));
+    let recent = timestamp.is_some_and(|timestamp| timestamp >= since);
+    match operator {
+        RuleOperator::InLastDays => recent,
+        RuleOperator::NotInLastDays => !recent,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn song(path: &str, artist: &str, genre: &str, year: u32) -> Song {
+        Song {
+            title: path.to_string(),
+            subtitle: artist.to_string(),
+            artists: vec![artist.to_string()],
+            genre: Some(genre.to_string()),
+            year: Some(year),
+            codec: "MP3".to_string(),
+            path: path.to_string(),
+            added_at: NOW - 3 * SECONDS_PER_DAY,
+            ..Song::default()
+        }
+    }
+
+    fn rule(field: RuleField, operator: RuleOperator, value: RuleValue) -> SmartRule {
+        SmartRule {
+            field,
+            operator,
+            value,
+        }
+    }
+
+    fn no_plays() -> Plays {
+        Plays::new(&[])
+    }
+
+    #[test]
+    fn text_rules_fold_case_and_accents() {
+        let song = song("/a.mp3", "Beyoncé", "R&B", 2003);
+        let plays = no_plays();
+        let is = rule(
+            RuleField::Artist,
+            RuleOperator::Is,
+            RuleValue::Text("beyonce".to_string()),
+        );
+        assert!(rule_matches(&is, &song, &plays, NOW));
+
+        let excluded = rule(
+            RuleField::Genre,
+            RuleOperator::DoesNotContain,
+            RuleValue::Text("r&b".to_string()),
+        );
+        assert!(!rule_matches(&excluded, &song, &plays, NOW));
+    }
+
+    #[test]
+    fn number_and_date_rules() {
+        let song = song("/a.mp3", "Artist", "Rock", 1999);
+        let plays = Plays::new(&[HistoryTrackRecord {
+            track_path: "/a.mp3".to_string(),
+            track_id: None,
+            play_count: 4,
+            last_played_at: NOW - 40 * SECONDS_PER_DAY,
+        }]);
+        let number = |field, operator, value| {
+            rule_matches(
+                &rule(field, operator, RuleValue::Number(value)),
+                &song,
+                &plays,
+                NOW,
+            )
+        };
+
+        assert!(number(RuleField::Year, RuleOperator::AtMost, 1999));
+        assert!(!number(RuleField::Year, RuleOperator::AtLeast, 2000));
+        assert!(number(RuleField::PlayCount, RuleOperator::Is, 4));
+        assert!(number(RuleField::Rating, RuleOperator::Is, 0));
+        assert!(number(RuleField::AddedAt, RuleOperator::InLastDays, 7));
+        assert!(number(
+            RuleField::LastPlayed,
+            RuleOperator::NotInLastDays,
+            30
+        ));
+        assert!(!number(RuleField::LastPlayed, RuleOperator::InLastDays, 30));
+    }
+
+    #[test]
+    fn mismatched_values_never_match() {
+        let song = song("/a.mp3", "Artist", "Rock", 1999);
+        let text_year = rule(
+            RuleField::Year,
+            RuleOperator::Is,
+            RuleValue::Text("1999".to_string()),
+        );
+        assert!(!rule_matches(&text_year, &song, &no_plays(), NOW));
+    }
+
+    #[test]
+    fn nested_groups() {
+        let definition: SmartPlaylistDefinition = serde_json::from_value(json!({
+            "matching": "all",
+            "rules": [
+                {"type": "rule", "field": "genre", "operator": "is", "value": "rock"},
+                {"type": "group", "matching": "any", "rules": [
+                    {"type": "rule", "field": "year", "operator": "at_most", "value": 1979},
+                    {"type": "rule", "field": "artist", "operator": "contains", "value": "queen"}
+                ]}
+            ]
+        }))
+        .unwrap();
+        definition.validate().unwrap();
+
+        let plays = no_plays();
+        let matching = |song: &Song| group_matches(&definition.rules, song, &plays, NOW);
+        assert!(matching(&song("/a.mp3", "Led Zeppelin", "Rock", 1971)));
+        assert!(matching(&song("/b.mp3", "Queen", "Rock", 1991)));
+        assert!(!matching(&song("/c.mp3", "Oasis", "Rock", 1995)));
+        assert!(!matching(&song("/d.mp3", "Queen", "Pop", 1975)));
+    }
+
+    #[test]
+    fn empty_top_level_group_matches_everything() {
+        let group = RuleGroup {
+            matching: RuleMatch::Any,
+            rules: Vec::new(),
+        };
+        let song = song("/a.mp3", "Artist", "Rock", 1999);
+        assert!(group_matches(&group, &song, &no_plays(), NOW));
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        let unknown_operator = serde_json::from_value::<SmartPlaylistDefinition>(json!({
+            "rules": [{"type": "rule", "field": "artist", "operator": "equals", "value": "x"}]
+        }));
+        assert!(unknown_operator.is_err());
+
+        let untagged = serde_json::from_value::<SmartPlaylistDefinition>(json!({
+            "rules": [{"field": "artist", "operator": "is", "value": "x"}]
+        }));
+        assert!(untagged.is_err());
+
+        let empty_group: SmartPlaylistDefinition = serde_json::from_value(json!({
+            "rules": [{"type": "group", "matching": "any", "rules": []}]
+        }))
+        .unwrap();
+        assert!(empty_group.validate().is_err());
+
+        let wrong_operator: SmartPlaylistDefinition = serde_json::from_value(json!({
+            "rules": [{"type": "rule", "field": "year", "operator": "contains", "value": 1999}]
+        }))
+        .unwrap();
+        assert!(wrong_operator.validate().is_err());
+    }
+
+    #[test]
+    fn evaluate_sorts_and_limits() {
+        let library: HashMap<String, Song> = [
+            song("/a.mp3", "A", "Rock", 1990),
+            song("/b.mp3", "B", "Rock", 2010),
+            song("/c.mp3", "C", "Rock", 2000),
+        ]
+        .into_iter()
+        .map(|song| (song.path.clone(), song))
+        .collect();
+        let definition: SmartPlaylistDefinition = serde_json::from_value(json!({
+            "sort": "year",
+            "direction": "desc",
+            "limit": 2
+        }))
+        .unwrap();
+
+        let paths: Vec<String> = evaluate(&definition, &library, &[], NOW)
+            .into_iter()
+            .map(|song| song.path)
+            .collect();
+        assert_eq!(paths, ["/b.mp3", "/c.mp3"]);
+    }
+}
@@ -1,5 +1,10 @@
 use crate::config::config::get_config_path;
-use rusqlite::{params, Connection};
+use crate::models::models::TrackStats;
+use crate::music::history::ListeningHistoryStore;
+use crate::music::library::MovedTrack;
+use crate::playlists::smart::SmartPlaylistDefinition;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::HashMap;
 use std::sync::Mutex;
 
 const FAVORITES_SLUG: &str = "favorites-tracks";
@@ -48,7 +53,33 @@ impl PlaylistStore {
                     PRIMARY KEY (playlist_id, track_path),
                     FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE
                  );
-                 CREATE INDEX IF NOT EXISTS idx_playlist_tracks_path ON playlist_tracks(track_path);",
+                 CREATE INDEX IF NOT EXISTS idx_playlist_tracks_path ON playlist_tracks(track_path);
+                 CREATE TABLE IF NOT EXISTS smart_playlists (
+                    playlist_id INTEGER PRIMARY KEY,
+                    definition TEXT NOT NULL,
+                    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE
+                 );
+                 CREATE TABLE IF NOT EXISTS track_stats (
+                    track_id TEXT PRIMARY KEY,
+                    rating INTEGER,
+                    added_at INTEGER
+                 );",
+            )
+            .expect("Failed to initialize playlists schema");
+
+        // Older databases predate stable track ids.
+        if connection
+            .prepare("SELECT track_id FROM playlist_tracks LIMIT 0")
+            .is_err()
+        {
+            connection
+                .execute("ALTER TABLE playlist_tracks ADD COLUMN track_id TEXT", [])
+                .expect("Failed to migrate playlists schema");
+        }
+        connection
+            .execute(
+                "CREATE INDEX IF NOT EXISTS idx_playlist_tracks_track_id ON playlist_tracks(track_id)",
+                [],
             )
             .expect("Failed to initialize playlists schema");
     }
@@ -66,7 +97,12 @@ impl PlaylistStore {
     pub fn get_all(&self) -> Result<Vec<PlaylistRecord>, String> {
         let connection = self.connection.lock().unwrap();
         let mut statement = connection
-            .prepare("SELECT slug, name FROM playlists ORDER BY name COLLATE NOCASE ASC")
+            .prepare(
+                "SELECT p.slug, p.name, s.playlist_id IS NOT NULL
+                 FROM playlists p
+                 LEFT JOIN smart_playlists s ON s.playlist_id = p.id
+                 ORDER BY p.name COLLATE NOCASE ASC",
+            )
             .map_err(|error| error.to_string())?;
 
         let rows = statement
@@ -74,6 +110,7 @@ impl PlaylistStore {
                 Ok(PlaylistRecord {
                     slug: row.get(0)?,
                     name: row.get(1)?,
+                    smart: row.get(2)?,
                 })
             })
             .map_err(|error| error.to_string())?;
@@ -108,27 +145,176 @@ impl PlaylistStore {
         Ok(paths)
     }
 
-    pub fn add_track(&self, playlist_slug: &str, track_path: &str) -> Result<bool, String> {
+    pub fn add_track(
+        &self,
+        playlist_slug: &str,
+        track_path: &str,
+        track_id: Option<&str>,
+    ) -> Result<bool, String> {
         let connection = self.connection.lock().unwrap();
 
-        let playlist_id: i64 = connection
+        let (playlist_id, smart): (i64, bool) = connection
             .query_row(
-                "SELECT id FROM playlists WHERE slug = ?1",
+                "SELECT p.id, s.playlist_id IS NOT NULL
+                 FROM playlists p
+                 LEFT JOIN smart_playlists s ON s.playlist_id = p.id
+                 WHERE p.slug = ?1",
                 params![playlist_slug],
-                |row| row.get(0),
+                |row| Ok((row.get(0)?, row.get(1)?)),
             )
             .map_err(|error| error.to_string())?;
+        if smart {
+            return Err("Smart playlists are filled by their rules".to_string());
+        }
 
         let inserted = connection
             .execute(
-                "INSERT OR IGNORE INTO playlist_tracks (playlist_id, track_path) VALUES (?1, ?2)",
-                params![playlist_id, track_path],
+                "INSERT OR IGNORE INTO playlist_tracks (playlist_id, track_path, track_id)
+                 VALUES (?1, ?2, ?3)",
+                params![playlist_id, track_path, track_id],
             )
             .map_err(|error| error.to_string())?;
 
         Ok(inserted > 0)
     }
 
+    /// Adds `tracks` to the manual playlist called `name`, creating it first
+    /// if there is none, and returns its slug.
+    pub fn add_tracks_to_named(
+        &self,
+        name: &str,
+        tracks: &[(String, String)],
+    ) -> Result<String, String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+
+        let existing: Option<(i64, String)> = transaction
+            .query_row(
+                "SELECT id, slug FROM playlists
+                 WHERE name = ?1 AND id NOT IN (SELECT playlist_id FROM smart_playlists)",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|error| error.to_string())?;
+
+        let (playlist_id, slug) = match existing {
+            Some(playlist) => playlist,
+            None => insert_playlist(&transaction, name)?,
+        };
+
+        for (track_path, track_id) in tracks {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO playlist_tracks (playlist_id, track_path, track_id)
+                     VALUES (?1, ?2, ?3)",
+                    params![playlist_id, track_path, track_id],
+                )
+                .map_err(|error| error.to_string())?;
+        }
+
+        transaction.commit().map_err(|error| error.to_string())?;
+        Ok(slug)
+    }
+
+    pub fn create_smart_playlist(
+        &self,
+        name: &str,
+        definition: &SmartPlaylistDefinition,
+    ) -> Result<String, String> {
+        definition.validate()?;
+        let definition = serde_json::to_string(definition).map_err(|error| error.to_string())?;
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+        let (playlist_id, slug) = insert_playlist(&transaction, name)?;
+        transaction
+            .execute(
+                "INSERT INTO smart_playlists (playlist_id, definition) VALUES (?1, ?2)",
+                params![playlist_id, definition],
+            )
+            .map_err(|error| error.to_string())?;
+
+        transaction.commit().map_err(|error| error.to_string())?;
+        Ok(slug)
+    }
+
+    /// Renames the smart playlist and replaces its rules. The slug stays.
+    pub fn update_smart_playlist(
+        &self,
+        playlist_slug: &str,
+        name: &str,
+        definition: &SmartPlaylistDefinition,
+    ) -> Result<(), String> {
+        definition.validate()?;
+        let definition = serde_json::to_string(definition).map_err(|error| error.to_string())?;
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+        let updated = transaction
+            .execute(
+                "UPDATE smart_playlists SET definition = ?2
+                 WHERE playlist_id = (SELECT id FROM playlists WHERE slug = ?1)",
+                params![playlist_slug, definition],
+            )
+            .map_err(|error| error.to_string())?;
+        if updated == 0 {
+            return Err("Smart playlist was not found".to_string());
+        }
+        transaction
+            .execute(
+                "UPDATE playlists SET name = ?2 WHERE slug = ?1",
+                params![playlist_slug, name],
+            )
+            .map_err(|error| error.to_string())?;
+
+        transaction.commit().map_err(|error| error.to_string())
+    }
+
+    /// `None` for manual playlists.
+    pub fn smart_definition(
+        &self,
+        playlist_slug: &str,
+    ) -> Result<Option<SmartPlaylistDefinition>, String> {
+        let connection = self.connection.lock().unwrap();
+        let definition: Option<String> = connection
+            .query_row(
+                "SELECT s.definition
+                 FROM smart_playlists s
+                 JOIN playlists p ON p.id = s.playlist_id
+                 WHERE p.slug = ?1",
+                params![playlist_slug],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|error| error.to_string())?;
+
+        definition
+            .map(|definition| serde_json::from_str(&definition).map_err(|error| error.to_string()))
+            .transpose()
+    }
+
+    pub fn delete_playlist(&self, playlist_slug: &str) -> Result<bool, String> {
+        if playlist_slug == FAVORITES_SLUG {
+            return Err("Favorite songs cannot be deleted".to_string());
+        }
+        let connection = self.connection.lock().unwrap();
+        let deleted = connection
+            .execute(
+                "DELETE FROM playlists WHERE slug = ?1",
+                params![playlist_slug],
+            )
+            .map_err(|error| error.to_string())?;
+
+        Ok(deleted > 0)
+    }
+
     pub fn remove_track(&self, playlist_slug: &str, track_path: &str) -> Result<bool, String> {
         let connection = self.connection.lock().unwrap();
 
@@ -144,6 +330,178 @@ impl PlaylistStore {
         Ok(deleted > 0)
     }
 
+    pub fn move_track_paths(&self, moved: &[MovedTrack]) -> Result<usize, String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+
+        let updated = move_paths_in(&transaction, moved)?;
+        transaction.commit().map_err(|error| error.to_string())?;
+        Ok(updated)
+    }
+
+    /// Moves playlist entries and listening history in one go: the playlist
+    /// transaction only commits once the history is saved, and the history
+    /// is restored if the commit fails.
+    pub fn move_track_paths_with_history(
+        &self,
+        moved: &[MovedTrack],
+        history: &ListeningHistoryStore,
+    ) -> Result<usize, String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+
+        let updated = move_paths_in(&transaction, moved)?;
+        history.move_paths_then(moved, || {
+            transaction.commit().map_err(|error| error.to_string())
+        })?;
+        Ok(updated)
+    }
+
+    pub fn track_references(&self) -> Result<Vec<PlaylistTrackRecord>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT p.slug, pt.track_path, pt.track_id
+                 FROM playlist_tracks pt
+                 JOIN playlists p ON p.id = pt.playlist_id",
+            )
+            .map_err(|error| error.to_string())?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok(PlaylistTrackRecord {
+                    playlist_slug: row.get(0)?,
+                    track_path: row.get(1)?,
+                    track_id: row.get(2)?,
+                })
+            })
+            .map_err(|error| error.to_string())?;
+
+        let mut tracks = Vec::new();
+        for row in rows {
+            tracks.push(row.map_err(|error| error.to_string())?);
+        }
+        Ok(tracks)
+    }
+
+    /// Points entries whose file is gone at the track with the same id, and
+    /// refreshes the stored id of entries whose file is still indexed.
+    pub fn relink_tracks(
+        &self,
+        ids_by_path: &HashMap<String, String>,
+        paths_by_id: &HashMap<String, String>,
+    ) -> Result<Vec<MovedTrack>, String> {
+        let mut connection = self.connection.lock().unwrap();
+        let rows: Vec<(i64, String, Option<String>)> = {
+            let mut statement = connection
+                .prepare("SELECT playlist_id, track_path, track_id FROM playlist_tracks")
+                .map_err(|error| error.to_string())?;
+            let rows = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .map_err(|error| error.to_string())?;
+            rows.collect::<Result<_, _>>()
+                .map_err(|error| error.to_string())?
+        };
+
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+        let mut moved = Vec::new();
+
+        for (playlist_id, track_path, track_id) in rows {
+            if let Some(current_id) = ids_by_path.get(&track_path) {
+                if track_id.as_ref() != Some(current_id) {
+                    transaction
+                        .execute(
+                            "UPDATE playlist_tracks SET track_id = ?3
+                             WHERE playlist_id = ?1 AND track_path = ?2",
+                            params![playlist_id, track_path, current_id],
+                        )
+                        .map_err(|error| error.to_string())?;
+                }
+                continue;
+            }
+
+            let Some(new_path) = track_id.and_then(|id| paths_by_id.get(&id)) else {
+                continue;
+            };
+
+            transaction
+                .execute(
+                    "UPDATE OR IGNORE playlist_tracks SET track_path = ?3
+                     WHERE playlist_id = ?1 AND track_path = ?2",
+                    params![playlist_id, track_path, new_path],
+                )
+                .map_err(|error| error.to_string())?;
+            transaction
+                .execute(
+                    "DELETE FROM playlist_tracks WHERE playlist_id = ?1 AND track_path = ?2",
+                    params![playlist_id, track_path],
+                )
+                .map_err(|error| error.to_string())?;
+
+            moved.push(MovedTrack {
+                from: track_path,
+                to: new_path.clone(),
+            });
+        }
+
+        transaction.commit().map_err(|error| error.to_string())?;
+        Ok(moved)
+    }
+
+    pub fn track_stats(&self) -> Result<HashMap<String, TrackStats>, String> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT track_id, rating, added_at FROM track_stats")
+            .map_err(|error| error.to_string())?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    TrackStats {
+                        rating: row.get(1)?,
+                        added_at: row.get(2)?,
+                    },
+                ))
+            })
+            .map_err(|error| error.to_string())?;
+
+        let mut stats = HashMap::new();
+        for row in rows {
+            let (track_id, track_stats) = row.map_err(|error| error.to_string())?;
+            stats.insert(track_id, track_stats);
+        }
+        Ok(stats)
+    }
+
+    /// Stores the given stats; fields left empty keep their current value.
+    pub fn merge_track_stats(&self, stats: &HashMap<String, TrackStats>) -> Result<(), String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| error.to_string())?;
+
+        for (track_id, track_stats) in stats {
+            transaction
+                .execute(
+                    "INSERT INTO track_stats (track_id, rating, added_at) VALUES (?1, ?2, ?3)
+                     ON CONFLICT(track_id) DO UPDATE SET
+                        rating = COALESCE(excluded.rating, rating),
+                        added_at = COALESCE(excluded.added_at, added_at)",
+                    params![track_id, track_stats.rating, track_stats.added_at],
+                )
+                .map_err(|error| error.to_string())?;
+        }
+
+        transaction.commit().map_err(|error| error.to_string())
+    }
+
     pub fn playlist_slugs_for_track(&self, track_path: &str) -> Result<Vec<String>, String> {
         let connection = self.connection.lock().unwrap();
         let mut statement = connection
@@ -167,8 +525,107 @@ impl PlaylistStore {
     }
 }
 
+/// Applies all moves in one pass, like the listening history does, so a
+/// path that is both a target and a source is only moved once.
+fn move_paths_in(transaction: &Transaction, moved: &[MovedTrack]) -> Result<usize, String> {
+    transaction
+        .execute_batch(
+            "CREATE TEMP TABLE IF NOT EXISTS moved_rows (
+                row_id INTEGER PRIMARY KEY,
+                to_path TEXT NOT NULL
+             );
+             DELETE FROM moved_rows;",
+        )
+        .map_err(|error| error.to_string())?;
+    for track in moved {
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO moved_rows (row_id, to_path)
+                 SELECT rowid, ?2 FROM playlist_tracks WHERE track_path = ?1",
+                params![track.from, track.to],
+            )
+            .map_err(|error| error.to_string())?;
+    }
+
+    // Moved rows first get a placeholder path, so they cannot collide
+    // with each other while they are renamed.
+    transaction
+        .execute(
+            "UPDATE playlist_tracks SET track_path = 'moving:' || rowid
+             WHERE rowid IN (SELECT row_id FROM moved_rows)",
+            [],
+        )
+        .map_err(|error| error.to_string())?;
+    let updated = transaction
+        .execute(
+            "UPDATE OR IGNORE playlist_tracks
+             SET track_path = (SELECT to_path FROM moved_rows WHERE row_id = playlist_tracks.rowid)
+             WHERE rowid IN (SELECT row_id FROM moved_rows)",
+            [],
+        )
+        .map_err(|error| error.to_string())?;
+    // Rows left behind already had the new path in the same playlist.
+    transaction
+        .execute(
+            "DELETE FROM playlist_tracks
+             WHERE rowid IN (SELECT row_id FROM moved_rows) AND track_path = 'moving:' || rowid",
+            [],
+        )
+        .map_err(|error| error.to_string())?;
+    Ok(updated)
+}
+
+/// Creates a playlist under a slug no other playlist uses yet.
+fn insert_playlist(transaction: &Transaction, name: &str) -> Result<(i64, String), String> {
+    let base = slugify(name);
+    let mut slug = base.clone();
+    let mut attempt = 2;
+    while transaction
+        .query_row(
+            "SELECT 1 FROM playlists WHERE slug = ?1",
+            params![slug],
+            |_| Ok(()),
+        )
+        .optional()
+        .map_err(|error| error.to_string())?
+        .is_some()
+    {
+        slug = format!("{}-{}", base, attempt);
+        attempt += 1;
+    }
+    transaction
+        .execute(
+            "INSERT INTO playlists (slug, name) VALUES (?1, ?2)",
+            params![slug, name],
+        )
+        .map_err(|error| error.to_string())?;
+    Ok((transaction.last_insert_rowid(), slug))
+}
+
+fn slugify(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "playlist".to_string()
+    } else {
+        slug
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PlaylistRecord {
     pub slug: String,
     pub name: String,
+    pub smart: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaylistTrackRecord {
+    pub playlist_slug: String,
+    pub track_path: String,
+    pub track_id: Option<String>,
 }
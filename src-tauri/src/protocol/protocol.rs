@@ -0,0 +1,177 @@
+use crate::commands::commands::artist_images_dir;
+use crate::music::artwork::{open_image, scale_down, thumbnail_name, write_jpeg};
+use crate::music::metadata::cover_cache_dir;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::UriSchemeResponder;
+
+pub const IMAGE_SCHEME: &str = "rift";
+
+// Requested sizes are rounded up to one of these so the disk cache holds a
+// handful of variants per image instead of one per layout width.
+const SERVED_SIZES: [u32; 5] = [64, 128, 256, 512, 1024];
+// A cover grid requests hundreds of images at once; a few workers keep
+// decoding from starving playback and the UI.
+const IMAGE_WORKERS: usize = 4;
+
+type ImageJob = (Request<Vec<u8>>, UriSchemeResponder);
+
+static IMAGE_JOBS: OnceLock<Sender<ImageJob>> = OnceLock::new();
+
+/// Serves `rift://localhost/cover/<file>` and `rift://localhost/artist/<file>`
+/// from the image caches, optionally scaled with `?size=<px>`. Decoding and
+/// resizing happen on a small pool of worker threads, off the webview thread.
+pub fn handle_image_request(request: Request<Vec<u8>>, responder: UriSchemeResponder) {
+    let jobs = IMAGE_JOBS.get_or_init(start_image_workers);
+    if let Err(mpsc::SendError((_, responder))) = jobs.send((request, responder)) {
+        responder.respond(error_response(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
+
+fn start_image_workers() -> Sender<ImageJob> {
+    let (sender, receiver) = mpsc::channel::<ImageJob>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..IMAGE_WORKERS {
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || loop {
+            let job = receiver.lock().unwrap().recv();
+            let Ok((request, responder)) = job else {
+                break;
+            };
+            // A panic while decoding must not shrink the pool.
+            let response = panic::catch_unwind(AssertUnwindSafe(|| image_response(&request)))
+                .unwrap_or_else(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR));
+            responder.respond(response);
+        });
+    }
+    sender
+}
+
+enum ImageKind {
+    Cover,
+    Artist,
+}
+
+fn image_response(request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let mut segments = request.uri().path().trim_start_matches('/').splitn(2, '/');
+    let kind = match segments.next() {
+        Some("cover") => ImageKind::Cover,
+        Some("artist") => ImageKind::Artist,
+        _ => return error_response(StatusCode::NOT_FOUND),
+    };
+    let Some(file_name) = segments.next().filter(|name| is_cache_file_name(name)) else {
+        return error_response(StatusCode::BAD_REQUEST);
+    };
+    let size = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("size="))
+            .and_then(|size| size.parse::<u32>().ok())
+    });
+
+    let dir = match kind {
+        ImageKind::Cover => cover_cache_dir(),
+        ImageKind::Artist => match artist_images_dir() {
+            Ok(dir) => dir,
+            Err(_) => return error_response(StatusCode::NOT_FOUND),
+        },
+    };
+    let original = dir.join(file_name);
+    if !original.is_file() {
+        return error_response(StatusCode::NOT_FOUND);
+    }
+
+    let path = match size.and_then(served_size) {
+        Some(size) => match sized_variant(&kind, &dir, file_name, size) {
+            Ok(path) => path,
+            Err(error) => {
+                eprintln!("Failed to resize {}: {}", file_name, error);
+                original
+            }
+        },
+        None => original,
+    };
+
+    match fs::read(&path) {
+        Ok(data) => {
+            // Cover names are content hashes, so they never change; artist
+            // images are refetched from time to time.
+            let cache_control = match kind {
+                ImageKind::Cover => "public, max-age=31536000, immutable",
+                ImageKind::Artist => "public, max-age=86400",
+            };
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type(&data))
+                .header(header::CACHE_CONTROL, cache_control)
+                .body(data)
+                .unwrap_or_else(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        Err(_) => error_response(StatusCode::NOT_FOUND),
+    }
+}
+
+fn error_response(status: StatusCode) -> Response<Vec<u8>> {
+    let mut response = Response::new(Vec::new());
+    *response.status_mut() = status;
+    response
+}
+
+/// Only plain file names from the caches; anything that could walk out of
+/// the cache directory is rejected.
+fn is_cache_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_'))
+}
+
+/// `None` means the original is larger than any served size.
+fn served_size(requested: u32) -> Option<u32> {
+    SERVED_SIZES.into_iter().find(|size| *size >= requested)
+}
+
+/// The JPEG of `file_name` scaled to `size`, generated on first request.
+fn sized_variant(
+    kind: &ImageKind,
+    dir: &Path,
+    file_name: &str,
+    size: u32,
+) -> Result<PathBuf, String> {
+    let variant = match kind {
+        ImageKind::Cover => dir.join(thumbnail_name(file_name, size)),
+        ImageKind::Artist => {
+            let stem = file_name.split('.').next().unwrap_or(file_name);
+            dir.join(format!("{}-{}.jpg", stem, size))
+        }
+    };
+    if variant.is_file() {
+        return Ok(variant);
+    }
+
+    let image = open_image(&dir.join(file_name))?;
+    write_jpeg(&scale_down(&image, size), &variant).map_err(|error| error.to_string())?;
+    Ok(variant)
+}
+
+fn content_type(data: &[u8]) -> &'static str {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if data.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
+        "image/png"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else if data.starts_with(b"BM") {
+        "image/bmp"
+    } else {
+        "application/octet-stream"
+    }
+}
@@ -0,0 +1,22 @@
+const IMAGE_SCHEME = "rift";
+
+export type ImageKind = "cover" | "artist";
+
+/**
+ * URL of a cached cover or artist image, served by the backend's `rift://`
+ * protocol and scaled to `size` pixels when given.
+ */
+export function imageUrl(
+  kind: ImageKind,
+  fileName: string,
+  size?: number,
+): string {
+  // Windows and Android webviews expose custom schemes over http.
+  const base =
+    navigator.userAgent.includes("Windows") ||
+    navigator.userAgent.includes("Android")
+      ? `http://${IMAGE_SCHEME}.localhost`
+      : `${IMAGE_SCHEME}://localhost`;
+  const url = `${base}/${kind}/${encodeURIComponent(fileName)}`;
+  return size ? `${url}?size=${size}` : url;
+}